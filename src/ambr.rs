@@ -1,20 +1,29 @@
-use amber::console::{Console, ConsoleTextKind};
-use amber::matcher::{QuickSearchMatcher, RegexMatcher, TbmMatcher};
-use amber::pipeline::{Pipeline, PipelineFork, PipelineInfo, PipelineJoin};
+use amber::console::{ColumnUnit, Console, ConsoleStyles, ConsoleTextKind};
+use amber::git::GitScope;
+use amber::matcher::{ChunkPool, QuickSearchMatcher, RegexMatcher, TbmMatcher};
+use amber::pipeline::{channel, Pipeline, PipelineInfo, PipelineJoin, Progress, SeqGate, Statistics};
 use amber::pipeline_finder::PipelineFinder;
-use amber::pipeline_matcher::PipelineMatcher;
+use amber::pipeline_matcher::{ContentsBudget, PipelineMatcher};
+use amber::pipeline_printer::PipelinePrinter;
 use amber::pipeline_replacer::PipelineReplacer;
-use amber::pipeline_sorter::PipelineSorter;
-use amber::util::{as_secsf64, decode_error, exit, get_config, handle_escape, read_from_file};
-use crossbeam::channel::unbounded;
+use amber::pipeline_sorter::{PipelineSorter, SortKey};
+use amber::util::{
+    as_secsf64, decode_error, exit, get_config, handle_escape, is_color_auto, is_color_auto_stderr, read_from_file,
+    read_path_list,
+};
+use crossbeam::channel::{select, tick, unbounded};
 use lazy_static::lazy_static;
 use serde::Deserialize;
+use serde_json::json;
 use std::cmp;
+use std::collections::HashMap;
 use std::fs;
-use std::io::Read;
+use std::io::{self, IsTerminal, Read};
 use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use structopt::{clap, StructOpt};
 
 // ---------------------------------------------------------------------------------------------------------------------
@@ -28,12 +37,12 @@ use structopt::{clap, StructOpt};
 #[structopt(setting(clap::AppSettings::DeriveDisplayOrder))]
 pub struct Opt {
     /// Keyword for search
-    #[structopt(name = "KEYWORD")]
-    pub keyword: String,
+    #[structopt(name = "KEYWORD", required_unless = "files")]
+    pub keyword: Option<String>,
 
     /// Keyword for replace
-    #[structopt(name = "REPLACEMENT")]
-    pub replacement: String,
+    #[structopt(name = "REPLACEMENT", required_unless = "files")]
+    pub replacement: Option<String>,
 
     /// Use file contents of KEYWORD as keyword for search
     #[structopt(long = "key-from-file")]
@@ -47,10 +56,68 @@ pub struct Opt {
     #[structopt(name = "PATHS")]
     pub paths: Vec<String>,
 
+    /// Read search paths from FILE ( "-" means stdin ) instead of PATHS
+    #[structopt(
+        long = "files-from",
+        value_name = "FILE",
+        conflicts_with_all = &["PATHS", "git-tracked", "git-modified", "git-staged", "git-since"]
+    )]
+    pub files_from: Option<String>,
+
+    /// Use NUL instead of newline as the path separator of --files-from
+    #[structopt(short = "0", long = "null", requires = "files-from")]
+    pub null: bool,
+
+    /// Print the files to be replaced instead of replacing, and why other files are skipped ( all positional
+    /// arguments are treated as PATHS )
+    #[structopt(long = "files")]
+    pub files: bool,
+
+    /// Show NUM lines after each match in interactive mode
+    #[structopt(short = "A", long = "after-context", value_name = "NUM")]
+    pub after_context: Option<usize>,
+
+    /// Show NUM lines before each match in interactive mode
+    #[structopt(short = "B", long = "before-context", value_name = "NUM")]
+    pub before_context: Option<usize>,
+
+    /// Show NUM lines before and after each match in interactive mode ( overridden by -A and -B )
+    #[structopt(short = "C", long = "context", value_name = "NUM")]
+    pub context: Option<usize>,
+
+    /// Sort results by KEY ( sorting by mtime or count prints nothing until all files are searched )
+    #[structopt(
+        long = "sort",
+        default_value = "none",
+        value_name = "KEY",
+        possible_values = &["none", "path", "mtime", "count"]
+    )]
+    pub sort: SortKey,
+
+    /// Reverse the order of --sort
+    #[structopt(long = "sort-reverse")]
+    pub sort_reverse: bool,
+
     /// Number of max threads
     #[structopt(long = "max-threads", default_value = &MAX_THREADS, value_name = "NUM")]
     pub max_threads: usize,
 
+    /// Number of threads searching files in parallel ( limited by --max-threads )
+    #[structopt(long = "matcher-threads", default_value = "8", value_name = "NUM")]
+    pub matcher_threads: usize,
+
+    /// Number of found files held to start the largest one first ( 0 means the found order )
+    #[structopt(long = "dispatch-window", default_value = "16", value_name = "NUM")]
+    pub dispatch_window: usize,
+
+    /// Capacity of the channels between the finder, matchers, sorter, and replacer ( 0 means unbounded )
+    #[structopt(long = "channel-capacity", default_value = "256", value_name = "NUM")]
+    pub channel_capacity: usize,
+
+    /// Number of results held to fix the output order ( at least 2 * --dispatch-window + 2 )
+    #[structopt(long = "reorder-window", default_value = "1024", value_name = "NUM")]
+    pub reorder_window: usize,
+
     /// File size per one thread
     #[structopt(long = "size-per-thread", default_value = "1048576", value_name = "BYTES")]
     pub size_per_thread: usize,
@@ -63,10 +130,43 @@ pub struct Opt {
     #[structopt(long = "mmap-bytes", default_value = "1048576", value_name = "BYTES")]
     pub mmap_bytes: u64,
 
+    /// Max bytes of file contents held from the matchers to the replacer, beyond which files are read again
+    #[structopt(long = "keep-bytes", default_value = "67108864", value_name = "BYTES")]
+    pub keep_bytes: usize,
+
     /// Verbose message
     #[structopt(long = "verbose")]
     pub verbose: bool,
 
+    /// Show the progress of the search on stderr ( ignored in interactive mode )
+    #[structopt(long = "progress")]
+    pub progress: bool,
+
+    /// Format of the statistics output
+    #[structopt(
+        long = "statistics-format",
+        default_value = "text",
+        value_name = "FORMAT",
+        possible_values = &["text", "json"]
+    )]
+    pub statistics_format: String,
+
+    /// Replace only files tracked by git
+    #[structopt(long = "git-tracked", conflicts_with_all = &["git-modified", "git-staged", "git-since"])]
+    pub git_tracked: bool,
+
+    /// Replace only files modified in the git working tree, and untracked files
+    #[structopt(long = "git-modified", conflicts_with_all = &["git-staged", "git-since"])]
+    pub git_modified: bool,
+
+    /// Replace only files staged in the git index
+    #[structopt(long = "git-staged", conflicts_with = "git-since")]
+    pub git_staged: bool,
+
+    /// Replace only files changed since the git revision
+    #[structopt(long = "git-since", value_name = "REV")]
+    pub git_since: Option<String>,
+
     /// Enable regular expression search
     #[structopt(short = "r", long = "regex", hidden = DEFAULT_FLAGS.regex)]
     pub regex: bool,
@@ -79,6 +179,23 @@ pub struct Opt {
     #[structopt(long = "row", hidden = DEFAULT_FLAGS.row)]
     pub row: bool,
 
+    /// Unit of the column printed by --row
+    #[structopt(
+        long = "column-unit",
+        default_value = "byte",
+        value_name = "UNIT",
+        possible_values = &["byte", "char", "utf16", "display"]
+    )]
+    pub column_unit: ColumnUnit,
+
+    /// Tab width for --column-unit display
+    #[structopt(long = "tab-width", default_value = "8", value_name = "NUM")]
+    pub tab_width: usize,
+
+    /// Trim lines of the interactive prompt to NUM characters around matches with "…" ( 0 means no limit )
+    #[structopt(long = "max-columns", default_value = "0", value_name = "NUM")]
+    pub max_columns: usize,
+
     /// Enable binary file search
     #[structopt(long = "binary", hidden = DEFAULT_FLAGS.binary)]
     pub binary: bool,
@@ -103,10 +220,27 @@ pub struct Opt {
     #[structopt(long = "symlink", hidden = DEFAULT_FLAGS.symlink)]
     pub symlink: bool,
 
-    /// Enable colored output
-    #[structopt(long = "color", hidden = DEFAULT_FLAGS.color)]
+    /// Colored output by --color=WHEN ( always if omitted ), where auto uses colors if the output is a terminal and
+    /// NO_COLOR is unset
+    #[structopt(
+        long = "color",
+        value_name = "WHEN",
+        possible_values = &["auto", "always", "never"],
+        // The value is given only after '=', so that bare --color doesn't take the following keyword
+        require_equals = true,
+        min_values = 0,
+        conflicts_with = "no-color"
+    )]
+    pub color_when: Option<Option<String>>,
+
+    /// Decided by --color, --no-color and the configuration
+    #[structopt(skip)]
     pub color: bool,
 
+    /// Same as `color` for stderr, which is decided separately by auto
+    #[structopt(skip)]
+    pub color_stderr: bool,
+
     /// Enable filename output
     #[structopt(long = "file", hidden = DEFAULT_FLAGS.file)]
     pub file: bool,
@@ -167,8 +301,8 @@ pub struct Opt {
     #[structopt(long = "no-symlink", hidden = !DEFAULT_FLAGS.symlink)]
     pub no_symlink: bool,
 
-    /// Disable colored output
-    #[structopt(long = "no-color", hidden = !DEFAULT_FLAGS.color)]
+    /// Disable colored output ( same as --color never )
+    #[structopt(long = "no-color")]
     pub no_color: bool,
 
     /// Disable filename output
@@ -226,6 +360,9 @@ struct DefaultFlags {
     symlink: bool,
     #[serde(default = "flag_true")]
     color: bool,
+    /// Styles of each kind of text by the `[colors]` table
+    #[serde(default)]
+    colors: HashMap<String, String>,
     #[serde(default = "flag_true")]
     file: bool,
     #[serde(default = "flag_true")]
@@ -285,7 +422,13 @@ impl DefaultFlags {
             opt.recursive
         };
         opt.symlink = if self.symlink { !opt.no_symlink } else { opt.symlink };
-        opt.color = if self.color { !opt.no_color } else { opt.color };
+        let color_when = opt.color_when.as_ref().map(|x| x.as_deref().unwrap_or("always"));
+        let color_auto = self.color && !opt.no_color;
+        (opt.color, opt.color_stderr) = match color_when {
+            Some("always") => (true, true),
+            Some("never") => (false, false),
+            _ => (color_auto && is_color_auto(), color_auto && is_color_auto_stderr()),
+        };
         opt.file = if self.file { !opt.no_file } else { opt.file };
         opt.skip_vcs = if self.skip_vcs { !opt.no_skip_vcs } else { opt.skip_vcs };
         opt.skip_gitignore = if self.skip_gitignore {
@@ -319,6 +462,9 @@ fn flag_false() -> bool {
     false
 }
 
+/// Interval of the periodic events handled by the main loop
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
 lazy_static! {
     static ref MAX_THREADS: String = format!("{}", num_cpus::get());
     static ref DEFAULT_FLAGS: DefaultFlags = DefaultFlags::load();
@@ -335,60 +481,124 @@ fn main() {
 
     // - Create opt ------------------------------------------------------------
 
-    let opt = Opt::from_args();
+    let opt = match Opt::from_args_safe() {
+        Ok(x) => x,
+        // Help and version are not errors
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            eprintln!("{}", e.message);
+            process::exit(2);
+        }
+    };
     let opt = DEFAULT_FLAGS.merge(opt);
 
     let mut console = Console::new();
     console.is_color = opt.color;
+    console.is_color_stderr = opt.color_stderr;
+
+    let mut styles = ConsoleStyles::default();
+    for (name, style) in &DEFAULT_FLAGS.colors {
+        if let Err(e) = styles.set(name, style) {
+            console.write(
+                ConsoleTextKind::Error,
+                &format!("Error: {} @ [colors] of ambr.toml\n", e),
+            );
+            exit(2, &mut console);
+        }
+    }
+    console.styles = styles;
 
     // - Set base path, keyword and replacement --------------------------------
+    let keyword_arg = opt.keyword.clone().unwrap_or_default();
+    let replacement_arg = opt.replacement.clone().unwrap_or_default();
+    let paths: Vec<String> = if opt.files {
+        opt.keyword
+            .iter()
+            .chain(opt.replacement.iter())
+            .chain(opt.paths.iter())
+            .cloned()
+            .collect()
+    } else {
+        opt.paths.clone()
+    };
+
+    // Interactive replace reads the answer from stdin
+    if opt.files_from.as_deref() == Some("-") && opt.interactive && !opt.files {
+        console.write(
+            ConsoleTextKind::Error,
+            "Error: \"--files-from -\" can't be used with interactive replace, add --no-interactive\n",
+        );
+        exit(2, &mut console);
+    }
+
     let mut base_paths: Vec<PathBuf> = Vec::new();
-    if opt.paths.is_empty() {
+    if let Some(ref files_from) = opt.files_from {
+        match read_path_list(files_from, opt.null) {
+            Ok(x) => base_paths = x,
+            Err(e) => {
+                console.write(
+                    ConsoleTextKind::Error,
+                    &format!("Error: {} @ {:?}\n", decode_error(&e), files_from),
+                );
+                exit(2, &mut console);
+            }
+        }
+    } else if paths.is_empty() {
         base_paths.push(PathBuf::from("./"));
     } else {
-        for p in &opt.paths {
+        for p in &paths {
             base_paths.push(PathBuf::from(p));
         }
     }
+    if opt.sort == SortKey::Path {
+        base_paths.sort();
+        if opt.sort_reverse {
+            base_paths.reverse();
+        }
+    }
 
-    let keyword = if opt.key_from_file {
-        match read_from_file(&opt.keyword) {
+    let keyword = if opt.files {
+        Vec::new()
+    } else if opt.key_from_file {
+        match read_from_file(&keyword_arg) {
             Ok(x) => {
                 if !x.is_empty() {
                     x
                 } else {
                     console.write(
                         ConsoleTextKind::Error,
-                        &format!("Error: file is empty @ {:?}\n", opt.keyword),
+                        &format!("Error: file is empty @ {:?}\n", keyword_arg),
                     );
-                    exit(1, &mut console);
+                    exit(2, &mut console);
                 }
             }
             Err(e) => {
                 console.write(
                     ConsoleTextKind::Error,
-                    &format!("Error: {} @ {:?}\n", decode_error(e.kind()), opt.keyword),
+                    &format!("Error: {} @ {:?}\n", decode_error(&e), keyword_arg),
                 );
-                exit(1, &mut console);
+                exit(2, &mut console);
             }
         }
     } else {
-        handle_escape(&opt.keyword).into_bytes()
+        handle_escape(&keyword_arg).into_bytes()
     };
 
-    let replacement = if opt.rep_from_file {
-        match read_from_file(&opt.replacement) {
+    let replacement = if opt.files {
+        Vec::new()
+    } else if opt.rep_from_file {
+        match read_from_file(&replacement_arg) {
             Ok(x) => x,
             Err(e) => {
                 console.write(
                     ConsoleTextKind::Error,
-                    &format!("Error: {} @ {:?}\n", decode_error(e.kind()), opt.replacement),
+                    &format!("Error: {} @ {:?}\n", decode_error(&e), replacement_arg),
                 );
-                exit(1, &mut console);
+                exit(2, &mut console);
             }
         }
     } else {
-        handle_escape(&opt.replacement).into_bytes()
+        handle_escape(&replacement_arg).into_bytes()
     };
 
     // ---------------------------------------------------------------------------------------------
@@ -400,81 +610,148 @@ fn main() {
     let id_replacer = 2;
     let id_matcher = 3;
 
-    let matcher_num = cmp::min(8, opt.max_threads);
+    let matcher_num = cmp::max(1, cmp::min(opt.matcher_threads, opt.max_threads));
 
+    // The channels from/to main are unbounded because main sends all paths before receiving
     let (tx_finder, rx_finder) = unbounded();
-    let (tx_replacer, rx_replacer) = unbounded();
+    let (tx_replacer, rx_replacer) = channel(opt.channel_capacity);
     let (tx_main, rx_main) = unbounded();
 
-    let mut tx_matcher = Vec::new();
+    // All matchers share one queue, so an idle matcher takes the next file
+    let (tx_matcher, rx_matcher) = channel(opt.channel_capacity);
     let mut rx_sorter = Vec::new();
 
+    let print_progress = opt.progress && !opt.interactive && io::stderr().is_terminal();
+
     let mut finder = PipelineFinder::new();
     let mut sorter = PipelineSorter::new(matcher_num);
     let mut replacer = PipelineReplacer::new(&keyword, &replacement, opt.regex);
+    let tmpfile_path = replacer.tmpfile_path.clone();
 
     finder.is_recursive = opt.recursive;
     finder.follow_symlink = opt.symlink;
     finder.skip_vcs = opt.skip_vcs;
     finder.skip_gitignore = opt.skip_gitignore;
-    finder.print_skipped = opt.skipped | opt.verbose;
+    finder.print_skipped = opt.skipped | opt.verbose | opt.files;
     finder.find_parent_ignore = opt.parent_ignore;
-    sorter.through = !opt.fixed_order;
+    finder.from_list = opt.files_from.is_some();
+    finder.dispatch_window = opt.dispatch_window;
+    finder.matcher_num = matcher_num;
+    finder.print_progress = print_progress;
+    finder.git_scope = if opt.git_tracked {
+        Some(GitScope::Tracked)
+    } else if opt.git_modified {
+        Some(GitScope::Modified)
+    } else if opt.git_staged {
+        Some(GitScope::Staged)
+    } else {
+        opt.git_since.clone().map(GitScope::Since)
+    };
+    if finder.git_scope.is_some() {
+        if let Err(e) = GitScope::check_command() {
+            console.write(ConsoleTextKind::Error, &format!("Error: {}\n", e));
+            exit(2, &mut console);
+        }
+    }
+    finder.sort_path = opt.sort == SortKey::Path;
+    finder.sort_reverse = opt.sort_reverse;
+    sorter.sort_key = opt.sort;
+    sorter.sort_reverse = opt.sort_reverse;
+    sorter.through = !opt.fixed_order && opt.sort == SortKey::None;
+    if !sorter.through {
+        // The finder keeps file numbers in flight within `2 * dispatch_window + 1`, so a narrower window would stall
+        let gate = Arc::new(SeqGate::new(cmp::max(opt.reorder_window, 2 * opt.dispatch_window + 2)));
+        finder.gate = Some(gate.clone());
+        sorter.gate = Some(gate);
+    }
     replacer.is_color = opt.color;
+    replacer.is_color_stderr = opt.color_stderr;
+    replacer.styles = styles;
     replacer.is_interactive = opt.interactive;
     replacer.preserve_time = opt.preserve_time;
     replacer.print_file = opt.file;
     replacer.print_column = opt.column;
     replacer.print_row = opt.row;
+    replacer.max_columns = opt.max_columns;
+    replacer.column_unit = opt.column_unit;
+    replacer.tab_width = opt.tab_width;
+    replacer.context_after = opt.after_context.or(opt.context).unwrap_or(0);
+    replacer.context_before = opt.before_context.or(opt.context).unwrap_or(0);
 
     let use_regex = opt.regex;
     let use_tbm = opt.tbm;
     let skip_binary = !opt.binary;
-    let print_skipped = opt.skipped | opt.verbose;
+    let print_skipped = opt.skipped | opt.verbose | opt.files;
+    let skip_search = opt.files;
     let print_search = opt.verbose;
     let binary_check_bytes = opt.bin_check_bytes;
     let mmap_bytes = opt.mmap_bytes;
+    // The contents of all files would be held until the sorter gets all results
+    let keep_contents = !opt.sort.is_buffered();
     let max_threads = opt.max_threads;
     let size_per_thread = opt.size_per_thread;
+    let pool = Arc::new(ChunkPool::new());
+    let contents_budget = Arc::new(ContentsBudget::new(opt.keep_bytes));
 
     for i in 0..matcher_num {
         let keyword = keyword.clone();
-        let (tx_in, rx_in) = unbounded();
-        let (tx_out, rx_out) = unbounded();
-        tx_matcher.push(tx_in);
+        let rx_in = rx_matcher.clone();
+        let pool = pool.clone();
+        let contents_budget = contents_budget.clone();
+        let (tx_out, rx_out) = channel(opt.channel_capacity);
         rx_sorter.push(rx_out);
 
         let _ = thread::Builder::new().name("matcher".to_string()).spawn(move || {
             if use_regex {
-                let m = RegexMatcher::new();
+                let mut m = RegexMatcher::new();
+                m.max_threads = max_threads;
+                m.size_per_thread = size_per_thread;
+                m.pool = Some(pool.clone());
                 let mut matcher = PipelineMatcher::new(m, &keyword);
                 matcher.skip_binary = skip_binary;
                 matcher.print_skipped = print_skipped;
                 matcher.print_search = print_search;
+                matcher.skip_search = skip_search;
+                matcher.print_progress = print_progress;
                 matcher.binary_check_bytes = binary_check_bytes;
                 matcher.mmap_bytes = mmap_bytes;
+                matcher.keep_contents = keep_contents;
+                matcher.contents_budget = Some(contents_budget.clone());
+                matcher.pool = Some(pool);
                 matcher.setup(id_matcher + i, rx_in, tx_out);
             } else if use_tbm {
                 let mut m = TbmMatcher::new();
                 m.max_threads = max_threads;
                 m.size_per_thread = size_per_thread;
+                m.pool = Some(pool.clone());
                 let mut matcher = PipelineMatcher::new(m, &keyword);
                 matcher.skip_binary = skip_binary;
                 matcher.print_skipped = print_skipped;
                 matcher.print_search = print_search;
+                matcher.skip_search = skip_search;
+                matcher.print_progress = print_progress;
                 matcher.binary_check_bytes = binary_check_bytes;
                 matcher.mmap_bytes = mmap_bytes;
+                matcher.keep_contents = keep_contents;
+                matcher.contents_budget = Some(contents_budget.clone());
+                matcher.pool = Some(pool);
                 matcher.setup(id_matcher + i, rx_in, tx_out);
             } else {
                 let mut m = QuickSearchMatcher::new();
                 m.max_threads = max_threads;
                 m.size_per_thread = size_per_thread;
+                m.pool = Some(pool.clone());
                 let mut matcher = PipelineMatcher::new(m, &keyword);
                 matcher.skip_binary = skip_binary;
                 matcher.print_skipped = print_skipped;
                 matcher.print_search = print_search;
+                matcher.skip_search = skip_search;
+                matcher.print_progress = print_progress;
                 matcher.binary_check_bytes = binary_check_bytes;
                 matcher.mmap_bytes = mmap_bytes;
+                matcher.keep_contents = keep_contents;
+                matcher.contents_budget = Some(contents_budget.clone());
+                matcher.pool = Some(pool);
                 matcher.setup(id_matcher + i, rx_in, tx_out);
             };
         });
@@ -488,14 +765,35 @@ fn main() {
         sorter.setup(id_sorter, rx_sorter, tx_replacer);
     });
 
+    let list_files = opt.files;
+    let is_color = opt.color;
+    let is_color_stderr = opt.color_stderr;
     let _ = thread::Builder::new().name("replacer".to_string()).spawn(move || {
-        replacer.setup(id_replacer, rx_replacer, tx_main);
+        if list_files {
+            let mut printer = PipelinePrinter::new();
+            printer.is_color = is_color;
+            printer.is_color_stderr = is_color_stderr;
+            printer.styles = styles;
+            printer.list_files = true;
+            printer.print_progress = print_progress;
+            printer.setup(id_replacer, rx_replacer, tx_main);
+        } else {
+            replacer.setup(id_replacer, rx_replacer, tx_main);
+        }
     });
 
     // ---------------------------------------------------------------------------------------------
     // Pipeline Flow
     // ---------------------------------------------------------------------------------------------
 
+    let (tx_cancel, rx_cancel) = unbounded();
+    let _ = ctrlc::set_handler(move || {
+        let _ = tx_cancel.send(());
+    });
+    #[cfg(not(windows))]
+    let c_lflag = amber::util::get_c_lflag();
+
+    let time_beg = Instant::now();
     let mut seq_no = 0;
     let _ = tx_finder.send(PipelineInfo::SeqBeg(seq_no));
     for p in base_paths {
@@ -518,36 +816,96 @@ fn main() {
         time_matcher_all.push(Duration::new(0, 0));
     }
 
+    let mut statistics = Statistics::default();
+    let mut progress: HashMap<usize, Progress> = HashMap::new();
+    let mut progress_path = None;
+
+    let ticker = tick(TICK_INTERVAL);
     loop {
-        match rx_main.try_recv() {
-            Ok(PipelineInfo::SeqEnd(_)) => break,
-            Ok(PipelineInfo::MsgTime(id, t0, t1)) if id == id_finder => {
-                time_finder_bsy = t0;
-                time_finder_all = t1;
-            }
-            Ok(PipelineInfo::MsgTime(id, t0, t1)) if id == id_sorter => {
-                time_sorter_bsy = t0;
-                time_sorter_all = t1;
-            }
-            Ok(PipelineInfo::MsgTime(id, t0, t1)) if id == id_replacer => {
-                time_replacer_bsy = t0;
-                time_replacer_all = t1;
+        select! {
+            recv(rx_main) -> msg => match msg {
+                Ok(PipelineInfo::SeqEnd(_)) | Err(_) => break,
+                Ok(PipelineInfo::MsgTime(id, t0, t1)) if id == id_finder => {
+                    time_finder_bsy = t0;
+                    time_finder_all = t1;
+                }
+                Ok(PipelineInfo::MsgTime(id, t0, t1)) if id == id_sorter => {
+                    time_sorter_bsy = t0;
+                    time_sorter_all = t1;
+                }
+                Ok(PipelineInfo::MsgTime(id, t0, t1)) if id == id_replacer => {
+                    time_replacer_bsy = t0;
+                    time_replacer_all = t1;
+                }
+                Ok(PipelineInfo::MsgTime(id, t0, t1)) => {
+                    time_matcher_bsy[id - id_matcher] = t0;
+                    time_matcher_all[id - id_matcher] = t1;
+                }
+                Ok(PipelineInfo::MsgProgress(id, x)) => {
+                    if x.current.is_some() {
+                        progress_path = x.current.clone();
+                    }
+                    progress.insert(id, x);
+                }
+                Ok(PipelineInfo::MsgInfo(_id, s)) => {
+                    let _lock = Console::lock();
+                    if print_progress {
+                        console.write_with_clear(ConsoleTextKind::Info, &format!("{}\n", s));
+                    } else {
+                        console.write(ConsoleTextKind::Info, &format!("{}\n", s));
+                    }
+                }
+                Ok(PipelineInfo::MsgStat(_id, x)) => statistics.add(&x),
+                Ok(PipelineInfo::MsgErr(_id, s)) => {
+                    statistics.errors += 1;
+                    let _lock = Console::lock();
+                    if print_progress {
+                        console.write_with_clear(ConsoleTextKind::Error, &format!("{}\n", s));
+                    } else {
+                        console.write(ConsoleTextKind::Error, &format!("{}\n", s));
+                    }
+                }
+                Ok(_) => (),
+            },
+            recv(ticker) -> _ => {
+                if print_progress {
+                    let mut total = Progress::default();
+                    for x in progress.values() {
+                        total.add(x);
+                    }
+                    total.current = progress_path.clone();
+
+                    let _lock = Console::lock();
+                    console.write_with_clear(ConsoleTextKind::Info, &format!("{}", total));
+                    console.flush();
+                }
             }
-            Ok(PipelineInfo::MsgTime(id, t0, t1)) => {
-                time_matcher_bsy[id - id_matcher] = t0;
-                time_matcher_all[id - id_matcher] = t1;
+            recv(rx_cancel) -> _ => {
+                if let Some(path) = tmpfile_path.lock().unwrap().take() {
+                    console.write(
+                        ConsoleTextKind::Info,
+                        &format!("\nCleanup temporary file: {:?}\n", path),
+                    );
+                    let _ = fs::remove_file(path);
+                }
+                #[cfg(not(windows))]
+                amber::util::set_c_lflag(c_lflag);
+                exit(130, &mut console);
             }
-            Ok(PipelineInfo::MsgInfo(_id, s)) => console.write(ConsoleTextKind::Info, &format!("{}\n", s)),
-            Ok(PipelineInfo::MsgErr(_id, s)) => console.write(ConsoleTextKind::Error, &format!("{}\n", s)),
-            Ok(_) => (),
-            Err(_) => (),
         }
     }
 
+    if print_progress {
+        console.write_with_clear(ConsoleTextKind::Info, "");
+    }
+
+    let time_all = time_beg.elapsed();
+
     // ---------------------------------------------------------------------------------------------
-    // Pipeline Flow
+    // Statistics
     // ---------------------------------------------------------------------------------------------
 
+    let sec_all = as_secsf64(time_all);
     let sec_finder_bsy = as_secsf64(time_finder_bsy);
     let sec_finder_all = as_secsf64(time_finder_all);
     let sec_sorter_bsy = as_secsf64(time_sorter_bsy);
@@ -562,12 +920,85 @@ fn main() {
         sec_matcher_all.push(as_secsf64(time_matcher_all[i]));
     }
 
-    if opt.statistics {
+    let (bytes_per_sec, files_per_sec) = if sec_all > 0.0 {
+        (
+            statistics.bytes_read as f64 / sec_all,
+            statistics.files_searched as f64 / sec_all,
+        )
+    } else {
+        (0.0, 0.0)
+    };
+
+    if opt.statistics && opt.statistics_format == "json" {
+        let stage = |name: &str, bsy: f64, all: f64| json!({ "name": name, "busy": bsy, "total": all });
+        let mut stages = vec![stage("find", sec_finder_bsy, sec_finder_all)];
+        for i in 0..matcher_num {
+            stages.push(stage(&format!("match{:02}", i), sec_matcher_bsy[i], sec_matcher_all[i]));
+        }
+        stages.push(stage("sort", sec_sorter_bsy, sec_sorter_all));
+        stages.push(stage("replace", sec_replacer_bsy, sec_replacer_all));
+
+        let value = json!({
+            "max_threads": opt.max_threads,
+            "elapsed": sec_all,
+            "bytes_per_sec": bytes_per_sec,
+            "files_per_sec": files_per_sec,
+            "counters": statistics,
+            "stages": stages,
+        });
+        console.is_color_stderr = false;
+        console.write(ConsoleTextKind::Info, &format!("{}\n", value));
+    } else if opt.statistics {
+        let skipped = &statistics.files_skipped;
         console.write(ConsoleTextKind::Info, "\nStatistics\n");
         console.write(
             ConsoleTextKind::Info,
             &format!("  Max threads: {}\n\n", opt.max_threads),
         );
+        console.write(ConsoleTextKind::Info, "  Files\n");
+        console.write(
+            ConsoleTextKind::Info,
+            &format!("    Walked   : {}\n", statistics.files_walked),
+        );
+        console.write(
+            ConsoleTextKind::Info,
+            &format!("    Searched : {}\n", statistics.files_searched),
+        );
+        console.write(
+            ConsoleTextKind::Info,
+            &format!(
+                "    Skipped  : {} ( binary: {}, vcs: {}, .gitignore: {}, not a file: {} )\n",
+                skipped.total(),
+                skipped.binary,
+                skipped.vcs,
+                skipped.gitignore,
+                skipped.not_file
+            ),
+        );
+        console.write(
+            ConsoleTextKind::Info,
+            &format!("    Errors   : {}\n\n", statistics.errors),
+        );
+        console.write(
+            ConsoleTextKind::Info,
+            &format!(
+                "  Bytes read : {} ( mmap: {} files, read: {} files )\n",
+                statistics.bytes_read, statistics.mmap_files, statistics.read_files
+            ),
+        );
+        console.write(
+            ConsoleTextKind::Info,
+            &format!("  Matches    : {}\n", statistics.matches),
+        );
+        console.write(ConsoleTextKind::Info, &format!("  Elapsed    : {}s\n", sec_all));
+        console.write(
+            ConsoleTextKind::Info,
+            &format!(
+                "  Throughput : {:.1} MiB/s, {:.1} files/s\n\n",
+                bytes_per_sec / (1024.0 * 1024.0),
+                files_per_sec
+            ),
+        );
         console.write(ConsoleTextKind::Info, "  Consumed time ( busy / total )\n");
         console.write(
             ConsoleTextKind::Info,
@@ -592,5 +1023,18 @@ fn main() {
         );
     }
 
-    exit(0, &mut console);
+    // The exit code is compatible with grep
+    let found = if opt.files {
+        statistics.files_walked != 0
+    } else {
+        statistics.matches != 0
+    };
+    let code = if statistics.errors != 0 {
+        2
+    } else if found {
+        0
+    } else {
+        1
+    };
+    exit(code, &mut console);
 }
@@ -1,16 +1,201 @@
 extern crate term;
 
 use crate::matcher::Match;
+use std::borrow::Cow;
 use std::io;
 use std::io::Write;
 use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
 use term::color::Color;
-use term::{StderrTerminal, StdoutTerminal};
+use term::{Attr, StderrTerminal, StdoutTerminal, Terminal};
+use unicode_width::UnicodeWidthChar;
+
+// ---------------------------------------------------------------------------------------------------------------------
+// ColumnUnit
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// The unit of the column number printed with a match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnUnit {
+    Byte,
+    Char,
+    /// UTF-16 code units, which LSP clients expect
+    Utf16,
+    /// Width on the terminal, where a tab advances to the next tab stop
+    Display,
+}
+
+impl FromStr for ColumnUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "byte" => Ok(ColumnUnit::Byte),
+            "char" => Ok(ColumnUnit::Char),
+            "utf16" => Ok(ColumnUnit::Utf16),
+            "display" => Ok(ColumnUnit::Display),
+            _ => Err(format!("unknown column unit: {}", s)),
+        }
+    }
+}
+
+impl ColumnUnit {
+    /// Get the column from 1 of the position following `prefix`, which is the line before the position.
+    pub fn column(&self, prefix: &[u8], tab_width: usize) -> usize {
+        let text = || String::from_utf8_lossy(prefix);
+        let len = match self {
+            ColumnUnit::Byte => prefix.len(),
+            ColumnUnit::Char => text().chars().count(),
+            ColumnUnit::Utf16 => text().chars().map(char::len_utf16).sum(),
+            ColumnUnit::Display => text().chars().fold(0, |width, c| {
+                if c == '\t' && tab_width != 0 {
+                    (width / tab_width + 1) * tab_width
+                } else {
+                    width + c.width().unwrap_or(0)
+                }
+            }),
+        };
+        len + 1
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// ConsoleStyle
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// The color and attributes of text, which is given as a string like "bold bright_yellow on_blue".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleStyle {
+    pub fg: Color,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub underline: bool,
+    pub italic: bool,
+}
+
+impl ConsoleStyle {
+    pub fn new(fg: Color) -> Self {
+        ConsoleStyle {
+            fg,
+            bg: None,
+            bold: false,
+            underline: false,
+            italic: false,
+        }
+    }
+
+    fn has_attr(&self) -> bool {
+        self.bg.is_some() || self.bold || self.underline || self.italic
+    }
+
+    fn parse_color(s: &str) -> Option<Color> {
+        let color = match s {
+            "black" => term::color::BLACK,
+            "red" => term::color::RED,
+            "green" => term::color::GREEN,
+            "yellow" => term::color::YELLOW,
+            "blue" => term::color::BLUE,
+            "magenta" => term::color::MAGENTA,
+            "cyan" => term::color::CYAN,
+            "white" => term::color::WHITE,
+            "bright_black" => term::color::BRIGHT_BLACK,
+            "bright_red" => term::color::BRIGHT_RED,
+            "bright_green" => term::color::BRIGHT_GREEN,
+            "bright_yellow" => term::color::BRIGHT_YELLOW,
+            "bright_blue" => term::color::BRIGHT_BLUE,
+            "bright_magenta" => term::color::BRIGHT_MAGENTA,
+            "bright_cyan" => term::color::BRIGHT_CYAN,
+            "bright_white" => term::color::BRIGHT_WHITE,
+            _ => return None,
+        };
+        Some(color)
+    }
+}
+
+impl FromStr for ConsoleStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ret = ConsoleStyle::new(term::color::WHITE);
+        for word in s.split_whitespace() {
+            match word {
+                "bold" => ret.bold = true,
+                "underline" => ret.underline = true,
+                "italic" => ret.italic = true,
+                _ => {
+                    if let Some(bg) = word.strip_prefix("on_").and_then(ConsoleStyle::parse_color) {
+                        ret.bg = Some(bg);
+                    } else if let Some(fg) = ConsoleStyle::parse_color(word) {
+                        ret.fg = fg;
+                    } else {
+                        return Err(format!("unknown color or style: {}", word));
+                    }
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// Styles of each `ConsoleTextKind`, which can be changed by the `[colors]` table of the configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleStyles {
+    pub filename: ConsoleStyle,
+    pub text: ConsoleStyle,
+    pub match_text: ConsoleStyle,
+    pub other: ConsoleStyle,
+    pub info: ConsoleStyle,
+    pub error: ConsoleStyle,
+}
+
+impl Default for ConsoleStyles {
+    fn default() -> Self {
+        ConsoleStyles {
+            filename: ConsoleStyle::new(term::color::BRIGHT_GREEN),
+            text: ConsoleStyle::new(term::color::WHITE),
+            match_text: ConsoleStyle::new(term::color::BRIGHT_YELLOW),
+            other: ConsoleStyle::new(term::color::BRIGHT_CYAN),
+            info: ConsoleStyle::new(term::color::BRIGHT_CYAN),
+            error: ConsoleStyle::new(term::color::BRIGHT_RED),
+        }
+    }
+}
+
+impl ConsoleStyles {
+    pub fn get(&self, kind: ConsoleTextKind) -> ConsoleStyle {
+        match kind {
+            ConsoleTextKind::Filename => self.filename,
+            ConsoleTextKind::Text => self.text,
+            ConsoleTextKind::MatchText => self.match_text,
+            ConsoleTextKind::Other => self.other,
+            ConsoleTextKind::Info => self.info,
+            ConsoleTextKind::Error => self.error,
+        }
+    }
+
+    /// Set the style of the kind by the name used in the configuration.
+    pub fn set(&mut self, name: &str, style: &str) -> Result<(), String> {
+        let style = style.parse()?;
+        match name {
+            "filename" => self.filename = style,
+            "text" => self.text = style,
+            "match" => self.match_text = style,
+            "other" => self.other = style,
+            "info" => self.info = style,
+            "error" => self.error = style,
+            _ => return Err(format!("unknown kind of text: {}", name)),
+        }
+        Ok(())
+    }
+}
 
 // ---------------------------------------------------------------------------------------------------------------------
 // Console
 // ---------------------------------------------------------------------------------------------------------------------
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConsoleTextKind {
     Filename,
     Text,
@@ -22,13 +207,29 @@ pub enum ConsoleTextKind {
 
 pub struct Console {
     pub is_color: bool,
+    /// Colors of errors and information, which are written to stderr
+    pub is_color_stderr: bool,
+    pub styles: ConsoleStyles,
+    /// Characters of text around matches kept in a line, where 0 means no limit
+    pub max_columns: usize,
     term_stdout: Box<StdoutTerminal>,
     term_stderr: Box<StderrTerminal>,
-    last_color: Color,
+    /// The style set to each terminal, which is not set again
+    last_style_stdout: Option<ConsoleStyle>,
+    last_style_stderr: Option<ConsoleStyle>,
 }
 
 const CR: u8 = 0x0d;
 const LF: u8 = 0x0a;
+const ELLIPSIS: &str = "…";
+
+/// Average bytes per line over which a file is regarded as minified
+const MINIFIED_LINE_BYTES: usize = 1000;
+
+static OUTPUT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Set if the reader of stdout has quit, which is shared by the consoles of all threads
+static STDOUT_CLOSED: AtomicBool = AtomicBool::new(false);
 
 impl Default for Console {
     fn default() -> Self {
@@ -46,7 +247,11 @@ impl Console {
                 process::exit(1);
             }),
             is_color: true,
-            last_color: term::color::BLACK,
+            is_color_stderr: true,
+            styles: ConsoleStyles::default(),
+            max_columns: 0,
+            last_style_stdout: None,
+            last_style_stderr: None,
         }
     }
 
@@ -62,27 +267,53 @@ impl Console {
         let _ = self.term_stdout.delete_line();
     }
 
+    /// Clear the current line of the stream which `kind` is written to, and write `val`.
     pub fn write_with_clear(&mut self, kind: ConsoleTextKind, val: &str) {
-        self.carriage_return();
-        self.delete_line();
+        match kind {
+            ConsoleTextKind::Error | ConsoleTextKind::Info => {
+                let _ = self.term_stderr.carriage_return();
+                let _ = self.term_stderr.delete_line();
+            }
+            _ => {
+                self.carriage_return();
+                self.delete_line();
+            }
+        }
         self.write(kind, val);
     }
 
+    /// Lock the output shared by threads, so that a line is not broken by a line from another thread.
+    pub fn lock() -> MutexGuard<'static, ()> {
+        OUTPUT_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
     pub fn write(&mut self, kind: ConsoleTextKind, val: &str) {
-        let color = match kind {
-            ConsoleTextKind::Filename => term::color::BRIGHT_GREEN,
-            ConsoleTextKind::Text => term::color::WHITE,
-            ConsoleTextKind::MatchText => term::color::BRIGHT_YELLOW,
-            ConsoleTextKind::Other => term::color::BRIGHT_CYAN,
-            ConsoleTextKind::Info => term::color::BRIGHT_CYAN,
-            ConsoleTextKind::Error => term::color::BRIGHT_RED,
-        };
+        let style = self.styles.get(kind);
 
         match kind {
-            ConsoleTextKind::Error => self.write_stderr(val, color),
-            ConsoleTextKind::Info => self.write_stderr(val, color),
-            _ => self.write_stdout(val, color),
+            ConsoleTextKind::Error => self.write_stderr(val, style),
+            ConsoleTextKind::Info => self.write_stderr(val, style),
+            _ => self.write_stdout(val, style),
+        }
+    }
+
+    /// Write `val` as a hyperlink to `url` by the escape sequence of OSC 8, which is ignored by older terminals.
+    pub fn write_hyperlink(&mut self, kind: ConsoleTextKind, val: &str, url: &str) {
+        self.write_raw(&format!("\x1b]8;;{}\x1b\\", url));
+        self.write(kind, val);
+        self.write_raw("\x1b]8;;\x1b\\");
+    }
+
+    fn write_raw(&mut self, val: &str) {
+        if Console::is_closed() {
+            return;
         }
+        Console::check_stdout(write!(self.term_stdout, "{}", val).map_err(term::Error::from));
+    }
+
+    /// Check whether the reader of stdout ( e.g. a pager ) has quit, after which the output to stdout is discarded.
+    pub fn is_closed() -> bool {
+        STDOUT_CLOSED.load(Ordering::Relaxed)
     }
 
     pub fn flush(&mut self) {
@@ -91,12 +322,15 @@ impl Console {
     }
 
     pub fn reset(&mut self) {
-        self.term_stdout.reset().unwrap_or_else(|_| {
-            process::exit(1);
-        });
-        self.term_stderr.reset().unwrap_or_else(|_| {
-            process::exit(1);
-        });
+        // Nothing to reset, and the escape sequence should not be mixed in plain output
+        if self.is_color && !Console::is_closed() {
+            Console::check_stdout(self.term_stdout.reset());
+            self.last_style_stdout = None;
+        }
+        if self.is_color_stderr {
+            Console::check_stderr(self.term_stderr.reset());
+            self.last_style_stderr = None;
+        }
     }
 
     pub fn get_line_beg(src: &[u8], beg: usize) -> usize {
@@ -128,71 +362,254 @@ impl Console {
         ret
     }
 
+    /// Get the start positions of all lines.
+    pub fn get_line_starts(src: &[u8]) -> Vec<usize> {
+        let mut ret = vec![0];
+        for (i, x) in src.iter().enumerate() {
+            if *x == LF && i + 1 < src.len() {
+                ret.push(i + 1);
+            }
+        }
+        ret
+    }
+
+    /// Get the index of the line including `pos`.
+    pub fn get_line_index(starts: &[usize], pos: usize) -> usize {
+        starts.partition_point(|x| *x <= pos) - 1
+    }
+
+    /// Get the range of the line at `index` excluding the line break.
+    pub fn get_line_range(src: &[u8], starts: &[usize], index: usize) -> (usize, usize) {
+        let beg = starts[index];
+        let mut end = starts.get(index + 1).copied().unwrap_or(src.len());
+        while end > beg && (src[end - 1] == LF || src[end - 1] == CR) {
+            end -= 1;
+        }
+        (beg, end)
+    }
+
+    /// Check whether the file seems minified, whose lines are too long to be printed.
+    pub fn is_minified(src: &[u8]) -> bool {
+        let lines = src.iter().filter(|x| **x == LF).count() + 1;
+        src.len() / lines > MINIFIED_LINE_BYTES
+    }
+
     pub fn write_to_linebreak(&mut self, src: &[u8], beg: usize, end: usize) {
         if beg < end {
-            self.write(ConsoleTextKind::Text, &String::from_utf8_lossy(&src[beg..end]));
+            // A whole line without matches keeps `max_columns` from the head
+            let trim = if beg == 0 || src[beg - 1] == LF || src[beg - 1] == CR {
+                Trim::Line
+            } else {
+                Trim::Tail
+            };
+            self.write(ConsoleTextKind::Text, &self.trim(&src[beg..end], trim));
         }
         self.write(ConsoleTextKind::Text, "\n");
     }
 
     pub fn write_match_part(&mut self, src: &[u8], m: &Match, beg: usize) {
         if beg < m.beg {
-            self.write(ConsoleTextKind::Text, &String::from_utf8_lossy(&src[beg..m.beg]));
+            // The text from the line start is trimmed at the head, and the text after the previous match at the middle
+            let trim = if beg == 0 || src[beg - 1] == LF || src[beg - 1] == CR {
+                Trim::Head
+            } else {
+                Trim::Middle
+            };
+            self.write(ConsoleTextKind::Text, &self.trim(&src[beg..m.beg], trim));
         }
-        self.write(ConsoleTextKind::MatchText, &String::from_utf8_lossy(&src[m.beg..m.end]));
+        self.write(ConsoleTextKind::MatchText, &self.trim(&src[m.beg..m.end], Trim::Match));
     }
 
     pub fn write_match_line(&mut self, src: &[u8], m: &Match) {
+        self.write_replace_line(src, m, &src[m.beg..m.end]);
+    }
+
+    pub fn write_replace_line(&mut self, src: &[u8], m: &Match, rep: &[u8]) {
         let beg = Console::get_line_beg(src, m.beg);
         let end = Console::get_line_end(src, m.end);
 
         if beg < m.beg {
-            self.write(ConsoleTextKind::Text, &String::from_utf8_lossy(&src[beg..m.beg]));
+            self.write(ConsoleTextKind::Text, &self.trim(&src[beg..m.beg], Trim::Head));
         }
-        self.write(ConsoleTextKind::MatchText, &String::from_utf8_lossy(&src[m.beg..m.end]));
+        self.write(ConsoleTextKind::MatchText, &self.trim(rep, Trim::Match));
         if m.end < end {
-            self.write(ConsoleTextKind::Text, &String::from_utf8_lossy(&src[m.end..end]));
+            self.write(ConsoleTextKind::Text, &self.trim(&src[m.end..end], Trim::Tail));
         }
         self.write(ConsoleTextKind::Text, "\n");
     }
 
-    pub fn write_replace_line(&mut self, src: &[u8], m: &Match, rep: &[u8]) {
-        let beg = Console::get_line_beg(src, m.beg);
-        let end = Console::get_line_end(src, m.end);
+    /// Trim `text` by `max_columns`, so that a line is printed as a window around the match with ellipses.
+    /// Text before and after a match keeps a half of `max_columns` each.
+    fn trim<'a>(&self, text: &'a [u8], trim: Trim) -> Cow<'a, str> {
+        let text = String::from_utf8_lossy(text);
+        let keep = match trim {
+            Trim::Line | Trim::Match => self.max_columns,
+            _ => self.max_columns / 2,
+        };
+        let len = text.chars().count();
+        if self.max_columns == 0 || len <= keep {
+            return text;
+        }
 
-        if beg < m.beg {
-            self.write(ConsoleTextKind::Text, &String::from_utf8_lossy(&src[beg..m.beg]));
+        let head = |n: usize| text.chars().take(n).collect::<String>();
+        let tail = |n: usize| text.chars().skip(len - n).collect::<String>();
+        let ret = match trim {
+            Trim::Head => format!("{}{}", ELLIPSIS, tail(keep)),
+            Trim::Tail | Trim::Line => format!("{}{}", head(keep), ELLIPSIS),
+            Trim::Middle | Trim::Match => format!("{}{}{}", head(keep / 2), ELLIPSIS, tail(keep - keep / 2)),
+        };
+        Cow::Owned(ret)
+    }
+
+    fn write_stdout(&mut self, val: &str, style: ConsoleStyle) {
+        if Console::is_closed() {
+            return;
         }
-        self.write(ConsoleTextKind::MatchText, &String::from_utf8_lossy(rep));
-        if m.end < end {
-            self.write(ConsoleTextKind::Text, &String::from_utf8_lossy(&src[m.end..end]));
+        if self.is_color && self.last_style_stdout != Some(style) {
+            Console::check_stdout(Console::set_style(
+                &mut *self.term_stdout,
+                style,
+                self.last_style_stdout,
+            ));
+            self.last_style_stdout = Some(style);
         }
-        self.write(ConsoleTextKind::Text, "\n");
+
+        Console::check_stdout(write!(self.term_stdout, "{}", val).map_err(term::Error::from));
     }
 
-    fn write_stdout(&mut self, val: &str, color: Color) {
-        if self.is_color && self.last_color != color {
-            self.term_stdout.fg(color).unwrap_or_else(|_| {
-                process::exit(1);
-            });
-            self.last_color = color;
+    fn write_stderr(&mut self, val: &str, style: ConsoleStyle) {
+        if self.is_color_stderr && self.last_style_stderr != Some(style) {
+            Console::check_stderr(Console::set_style(
+                &mut *self.term_stderr,
+                style,
+                self.last_style_stderr,
+            ));
+            self.last_style_stderr = Some(style);
         }
 
-        write!(self.term_stdout, "{}", val).unwrap_or_else(|_| {
-            process::exit(1);
-        });
+        Console::check_stderr(write!(self.term_stderr, "{}", val).map_err(term::Error::from));
     }
 
-    fn write_stderr(&mut self, val: &str, color: Color) {
-        if self.is_color && self.last_color != color {
-            self.term_stderr.fg(color).unwrap_or_else(|_| {
-                process::exit(1);
-            });
-            self.last_color = color;
+    /// Stop the output to stdout if its reader ( e.g. a pager ) has quit, so that the pipeline is stopped by
+    /// `is_closed`, otherwise exit as an error.
+    fn check_stdout(ret: term::Result<()>) {
+        match ret {
+            Err(term::Error::Io(ref x)) if x.kind() == io::ErrorKind::BrokenPipe => {
+                STDOUT_CLOSED.store(true, Ordering::Relaxed);
+            }
+            Err(_) => process::exit(1),
+            Ok(_) => (),
+        }
+    }
+
+    /// Ignore the broken pipe of stderr, where nothing can be told, otherwise exit as an error.
+    fn check_stderr(ret: term::Result<()>) {
+        match ret {
+            Err(term::Error::Io(ref x)) if x.kind() == io::ErrorKind::BrokenPipe => (),
+            Err(_) => process::exit(1),
+            Ok(_) => (),
+        }
+    }
+
+    fn set_style<T: Terminal + ?Sized>(
+        term: &mut T,
+        style: ConsoleStyle,
+        last: Option<ConsoleStyle>,
+    ) -> term::Result<()> {
+        // Attributes can't be cleared one by one
+        if last.is_some_and(|x| x.has_attr()) {
+            term.reset()?;
+        }
+        let mut attrs = vec![Attr::ForegroundColor(style.fg)];
+        if let Some(bg) = style.bg {
+            attrs.push(Attr::BackgroundColor(bg));
+        }
+        if style.bold {
+            attrs.push(Attr::Bold);
+        }
+        if style.underline {
+            attrs.push(Attr::Underline(true));
+        }
+        if style.italic {
+            attrs.push(Attr::Italic(true));
+        }
+        for attr in attrs {
+            // An attribute which the terminal doesn't support is ignored
+            if term.supports_attr(attr) {
+                term.attr(attr)?;
+            }
         }
+        Ok(())
+    }
+}
+
+/// Part of a line to be trimmed by `max_columns`.
+#[derive(Clone, Copy)]
+enum Trim {
+    /// Text before a match, which keeps the tail
+    Head,
+    /// Text after a match, which keeps the head
+    Tail,
+    /// Text between matches, which keeps both ends
+    Middle,
+    /// A line without matches, which keeps the head up to `max_columns`
+    Line,
+    /// Matched text, which keeps both ends up to `max_columns`
+    Match,
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// Test
+// ---------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_column_unit() {
+        let prefix = "a\tあ😀".as_bytes();
+        assert_eq!(ColumnUnit::Byte.column(prefix, 8), 10);
+        assert_eq!(ColumnUnit::Char.column(prefix, 8), 5);
+        assert_eq!(ColumnUnit::Utf16.column(prefix, 8), 6);
+        assert_eq!(ColumnUnit::Display.column(prefix, 8), 13);
+        assert_eq!(ColumnUnit::Display.column(prefix, 4), 9);
+        assert_eq!(ColumnUnit::Char.column(b"", 8), 1);
+        assert_eq!("utf16".parse::<ColumnUnit>(), Ok(ColumnUnit::Utf16));
+        assert!("word".parse::<ColumnUnit>().is_err());
+    }
+
+    #[test]
+    fn test_max_columns() {
+        let mut console = Console::new();
+        let src = b"0123456789abc0123456789\nxyz";
+        assert_eq!(console.trim(src, Trim::Head), "0123456789abc0123456789\nxyz");
+
+        console.max_columns = 8;
+        assert_eq!(console.trim(&src[..10], Trim::Head), "…6789");
+        assert_eq!(console.trim(&src[13..23], Trim::Tail), "0123…");
+        assert_eq!(console.trim(&src[..10], Trim::Middle), "01…89");
+        assert_eq!(console.trim(&src[..10], Trim::Match), "0123…6789");
+        assert_eq!(console.trim(&src[24..], Trim::Tail), "xyz");
+        assert_eq!(console.trim(&src[..10], Trim::Line), "01234567…");
+        assert_eq!(console.trim("あいうえおか".as_bytes(), Trim::Head), "…うえおか");
+
+        assert!(Console::is_minified(&[b'a'; 4096]));
+        assert!(!Console::is_minified(&src[..]));
+    }
+
+    #[test]
+    fn test_console_styles() {
+        let mut styles = ConsoleStyles::default();
+        assert_eq!(styles.get(ConsoleTextKind::MatchText).fg, term::color::BRIGHT_YELLOW);
+
+        styles.set("match", "bold red on_blue").unwrap();
+        let style = styles.get(ConsoleTextKind::MatchText);
+        assert_eq!(style.fg, term::color::RED);
+        assert_eq!(style.bg, Some(term::color::BLUE));
+        assert!(style.bold && !style.underline);
 
-        write!(self.term_stderr, "{}", val).unwrap_or_else(|_| {
-            process::exit(1);
-        });
+        assert!(styles.set("match", "pink").is_err());
+        assert!(styles.set("heading", "red").is_err());
     }
 }
@@ -0,0 +1,380 @@
+use crate::console::ConsoleTextKind;
+use crate::matcher::Match;
+use std::path::Path;
+
+// ---------------------------------------------------------------------------------------------------------------------
+// Template
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// A template of the line printed for each match, which is given by `--format`.
+///
+/// - `{name}` is replaced by a field of the match ( see `Field` ), and `{N}` by the capture group N.
+/// - `{color:KIND}` writes the following text with the color of KIND ( path, text, match or other ), and `{/color}`
+///   returns to the color of text.
+/// - `{{` and `}}` are literal braces, and `\n`, `\r`, `\t` and `\\` are escaped as the keyword.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+    pattern: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Field(Field),
+    Color(ConsoleTextKind),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// `{path}`: the path as found
+    Path,
+    /// `{relpath}`: the path relative to the current directory
+    RelPath,
+    /// `{line}`: the line number from 1
+    Line,
+    /// `{col}`: the column from 1 counted by `--column-unit`
+    Column,
+    /// `{begin}`, `{end}`: the byte offsets of the match in the file
+    Begin,
+    End,
+    /// `{match}`: the matched text
+    Match,
+    /// `{line_text}`: the line including the match without the line break
+    LineText,
+    /// `{N}`: the capture group N, where 0 is the whole match
+    Group(usize),
+    /// `{pattern}`: the keyword given by `set_pattern`
+    Pattern,
+    /// `{count}`: the number of matches in the file
+    Count,
+}
+
+/// Values of a match rendered by `Template`.
+pub struct FormatContext<'a> {
+    pub path: &'a Path,
+    pub relpath: &'a Path,
+    pub src: &'a [u8],
+    pub m: &'a Match,
+    /// Line number and column from 1
+    pub line: usize,
+    pub column: usize,
+    /// The range of the line including the match without the line break
+    pub line_range: (usize, usize),
+    pub count: usize,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Template, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.peek() {
+                    Some('n') => literal.push('\n'),
+                    Some('r') => literal.push('\r'),
+                    Some('t') => literal.push('\t'),
+                    Some('\\') => literal.push('\\'),
+                    _ => {
+                        literal.push('\\');
+                        continue;
+                    }
+                },
+                '}' => {
+                    if chars.peek() != Some(&'}') {
+                        return Err(String::from("unmatched '}' in the format ( use '}}' for a brace )"));
+                    }
+                    literal.push('}');
+                }
+                '{' if chars.peek() == Some(&'{') => literal.push('{'),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(x) => name.push(x),
+                            None => return Err(String::from("unmatched '{' in the format ( use '{{' for a brace )")),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Template::parse_placeholder(&name)?);
+                    continue;
+                }
+                _ => {
+                    literal.push(c);
+                    continue;
+                }
+            }
+            // Consume the second character of the escape
+            chars.next();
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Template {
+            segments,
+            pattern: String::new(),
+        })
+    }
+
+    fn parse_placeholder(name: &str) -> Result<Segment, String> {
+        let field = match name {
+            "path" => Field::Path,
+            "relpath" => Field::RelPath,
+            "line" => Field::Line,
+            "col" => Field::Column,
+            "begin" => Field::Begin,
+            "end" => Field::End,
+            "match" => Field::Match,
+            "line_text" => Field::LineText,
+            "pattern" => Field::Pattern,
+            "count" => Field::Count,
+            "/color" => return Ok(Segment::Color(ConsoleTextKind::Text)),
+            _ => {
+                if let Some(kind) = name.strip_prefix("color:") {
+                    let kind = match kind {
+                        "path" => ConsoleTextKind::Filename,
+                        "text" => ConsoleTextKind::Text,
+                        "match" => ConsoleTextKind::MatchText,
+                        "other" => ConsoleTextKind::Other,
+                        _ => return Err(format!("unknown color '{}' in the format", kind)),
+                    };
+                    return Ok(Segment::Color(kind));
+                }
+                match name.parse::<usize>() {
+                    Ok(x) => Field::Group(x),
+                    Err(_) => return Err(format!("unknown placeholder '{{{}}}' in the format", name)),
+                }
+            }
+        };
+        Ok(Segment::Field(field))
+    }
+
+    /// Check whether the template uses capture groups, which need captures of the regex.
+    pub fn uses_groups(&self) -> bool {
+        self.max_group() > 0
+    }
+
+    /// Get the largest capture group N of `{N}`, where 0 means no capture group is used.
+    pub fn max_group(&self) -> usize {
+        self.segments
+            .iter()
+            .filter_map(|x| match x {
+                Segment::Field(Field::Group(n)) => Some(*n),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Set the keyword rendered by `{pattern}`.
+    pub fn set_pattern(&mut self, pattern: &str) {
+        self.pattern = String::from(pattern);
+    }
+
+    /// Render a match to parts of text with the kind to be written.
+    pub fn render(&self, ctx: &FormatContext) -> Vec<(ConsoleTextKind, String)> {
+        let mut ret = Vec::new();
+        let mut kind = ConsoleTextKind::Text;
+        for segment in &self.segments {
+            let text = match segment {
+                Segment::Literal(x) => x.clone(),
+                Segment::Field(x) => self.field(ctx, *x),
+                Segment::Color(x) => {
+                    kind = *x;
+                    continue;
+                }
+            };
+            ret.push((kind, text));
+        }
+        ret
+    }
+
+    fn field(&self, ctx: &FormatContext, field: Field) -> String {
+        let text = |beg: usize, end: usize| String::from_utf8_lossy(&ctx.src[beg..end]).into_owned();
+        match field {
+            Field::Path => ctx.path.to_string_lossy().into_owned(),
+            Field::RelPath => ctx.relpath.to_string_lossy().into_owned(),
+            Field::Line => format!("{}", ctx.line),
+            Field::Column => format!("{}", ctx.column),
+            Field::Begin => format!("{}", ctx.m.beg),
+            Field::End => format!("{}", ctx.m.end),
+            Field::Match | Field::Group(0) => text(ctx.m.beg, ctx.m.end),
+            Field::LineText => text(ctx.line_range.0, ctx.line_range.1),
+            Field::Group(n) => match ctx.m.sub_match.get(n - 1) {
+                Some(Some(x)) => text(x.beg, x.end),
+                _ => String::new(),
+            },
+            Field::Pattern => self.pattern.clone(),
+            Field::Count => format!("{}", ctx.count),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// HyperlinkFormat
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// A template of the URL of a path written as a hyperlink, which is given by `--hyperlink-format`.
+///
+/// `{path}` is replaced by the absolute path, and `{line}`, `{col}` by the position from 1.
+/// A name of the presets below can be given instead of a template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperlinkFormat {
+    template: String,
+}
+
+const HYPERLINK_PRESETS: &[(&str, &str)] = &[
+    ("file", "file://{path}"),
+    ("vscode", "vscode://file{path}:{line}:{col}"),
+    ("vscode-insiders", "vscode-insiders://file{path}:{line}:{col}"),
+    ("vscodium", "vscodium://file{path}:{line}:{col}"),
+    ("cursor", "cursor://file{path}:{line}:{col}"),
+];
+
+impl HyperlinkFormat {
+    pub fn parse(text: &str) -> Result<HyperlinkFormat, String> {
+        let template = match HYPERLINK_PRESETS.iter().find(|(name, _)| *name == text) {
+            Some((_, x)) => String::from(*x),
+            None => String::from(text),
+        };
+
+        let mut rest = template.as_str();
+        while let Some(beg) = rest.find('{') {
+            let end = match rest[beg..].find('}') {
+                Some(x) => beg + x,
+                None => return Err(String::from("unmatched '{' in the hyperlink format")),
+            };
+            match &rest[beg + 1..end] {
+                "path" | "line" | "col" => (),
+                x => return Err(format!("unknown placeholder '{{{}}}' in the hyperlink format", x)),
+            }
+            rest = &rest[end + 1..];
+        }
+        if !template.contains("{path}") {
+            return Err(String::from("the hyperlink format needs '{path}'"));
+        }
+        Ok(HyperlinkFormat { template })
+    }
+
+    pub fn url(&self, path: &Path, line: usize, column: usize) -> String {
+        self.template
+            .replace("{path}", &encode_path(&path.to_string_lossy()))
+            .replace("{line}", &format!("{}", line))
+            .replace("{col}", &format!("{}", column))
+    }
+}
+
+/// Percent-encode characters of a path which can't be used in a URL as is.
+fn encode_path(path: &str) -> String {
+    let mut ret = String::new();
+    for x in path.bytes() {
+        match x {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' | b':' => ret.push(x as char),
+            _ => ret.push_str(&format!("%{:02X}", x)),
+        }
+    }
+    ret
+}
+
+/// Get `path` relative to `base`, where a path outside of `base` is returned as is.
+pub fn relative_path<'a>(path: &'a Path, base: &Path) -> &'a Path {
+    if path.is_absolute() {
+        path.strip_prefix(base).unwrap_or(path)
+    } else {
+        path.strip_prefix(".").unwrap_or(path)
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// Test
+// ---------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, ctx: &FormatContext) -> String {
+        let mut template = Template::parse(template).unwrap();
+        template.set_pattern("g.i");
+        template.render(ctx).into_iter().map(|(_, x)| x).collect()
+    }
+
+    #[test]
+    fn test_template() {
+        let src = b"abc\ndef ghi\r\njkl\n";
+        let m = Match {
+            beg: 8,
+            end: 11,
+            sub_match: vec![Some(Match {
+                beg: 9,
+                end: 10,
+                sub_match: Vec::new(),
+            })],
+        };
+        let ctx = FormatContext {
+            path: Path::new("./src/a.txt"),
+            relpath: relative_path(Path::new("./src/a.txt"), Path::new("/work")),
+            src,
+            m: &m,
+            line: 2,
+            column: 5,
+            line_range: (4, 11),
+            count: 3,
+        };
+
+        assert_eq!(
+            render("{path}:{line}:{col}: {match} | {line_text}", &ctx),
+            "./src/a.txt:2:5: ghi | def ghi"
+        );
+        assert_eq!(
+            render("{relpath}\\t{begin}-{end} {0}/{1}/{2} {pattern} {count}", &ctx),
+            "src/a.txt\t8-11 ghi/h/ g.i 3"
+        );
+        assert_eq!(render("{{{line}}}\\n\\x", &ctx), "{2}\n\\x");
+
+        let template = Template::parse("{color:path}{path}{/color}:{color:match}{match}").unwrap();
+        let parts = template.render(&ctx);
+        assert_eq!(parts[0], (ConsoleTextKind::Filename, String::from("./src/a.txt")));
+        assert_eq!(parts[1], (ConsoleTextKind::Text, String::from(":")));
+        assert_eq!(parts[2], (ConsoleTextKind::MatchText, String::from("ghi")));
+        assert!(!template.uses_groups());
+        assert!(Template::parse("{1}").unwrap().uses_groups());
+        assert!(!Template::parse("{0}").unwrap().uses_groups());
+        assert_eq!(Template::parse("{2}{0}{1}").unwrap().max_group(), 2);
+
+        assert!(Template::parse("{line").is_err());
+        assert!(Template::parse("line}").is_err());
+        assert!(Template::parse("{name}").is_err());
+        assert!(Template::parse("{color:blue}").is_err());
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let base = Path::new("/work");
+        assert_eq!(relative_path(Path::new("/work/src/a.rs"), base), Path::new("src/a.rs"));
+        assert_eq!(relative_path(Path::new("/other/a.rs"), base), Path::new("/other/a.rs"));
+        assert_eq!(relative_path(Path::new("./a.rs"), base), Path::new("a.rs"));
+        assert_eq!(relative_path(Path::new("a.rs"), base), Path::new("a.rs"));
+    }
+
+    #[test]
+    fn test_hyperlink_format() {
+        let path = Path::new("/work/src/a b.rs");
+        let format = HyperlinkFormat::parse("vscode").unwrap();
+        assert_eq!(format.url(path, 3, 5), "vscode://file/work/src/a%20b.rs:3:5");
+        let format = HyperlinkFormat::parse("file").unwrap();
+        assert_eq!(format.url(path, 3, 5), "file:///work/src/a%20b.rs");
+        let format = HyperlinkFormat::parse("editor://open?file={path}&line={line}").unwrap();
+        assert_eq!(format.url(path, 3, 5), "editor://open?file=/work/src/a%20b.rs&line=3");
+
+        assert!(HyperlinkFormat::parse("vscode://file").is_err());
+        assert!(HyperlinkFormat::parse("x://{path}:{row}").is_err());
+        assert!(HyperlinkFormat::parse("x://{path").is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::util::bytes_to_path;
+use std::ffi::OsStr;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// ---------------------------------------------------------------------------------------------------------------------
+// GitScope
+// ---------------------------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitScope {
+    /// Files tracked in the index
+    Tracked,
+    /// Files modified in the working tree, and untracked files which are not ignored
+    Modified,
+    /// Files staged in the index
+    Staged,
+    /// Files changed in the working tree since the revision
+    Since(String),
+}
+
+impl GitScope {
+    /// Check that the `git` command, which lists the files of every scope, can be run.
+    pub fn check_command() -> Result<(), Error> {
+        Command::new("git").arg("--version").output().map_err(git_error)?;
+        Ok(())
+    }
+
+    fn args(&self) -> Vec<&str> {
+        match self {
+            GitScope::Tracked => vec!["ls-files", "-z", "--cached"],
+            GitScope::Modified => vec!["ls-files", "-z", "--modified", "--others", "--exclude-standard"],
+            GitScope::Staged => vec!["diff", "-z", "--name-only", "--relative", "--diff-filter=d", "--cached"],
+            GitScope::Since(rev) => vec!["diff", "-z", "--name-only", "--relative", "--diff-filter=d", rev],
+        }
+    }
+
+    /// List the files under `base` which are selected by this scope.
+    ///
+    /// The returned paths are prefixed by `base`, and may include files which no longer exist in the working tree.
+    pub fn list_files(&self, base: &Path) -> Result<Vec<PathBuf>, Error> {
+        let (dir, pathspec) = if base.is_dir() {
+            (base.to_path_buf(), OsStr::new("."))
+        } else {
+            let dir = base.parent().map(|x| x.to_path_buf()).unwrap_or_default();
+            let name = base.file_name().unwrap_or(base.as_os_str());
+            (dir, name)
+        };
+        let work_dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir.as_path()
+        };
+
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(work_dir)
+            .args(self.args())
+            .arg("--")
+            .arg(pathspec)
+            .output()
+            .map_err(git_error)?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let msg = stderr.lines().next().unwrap_or("git command failed").trim().to_string();
+            return Err(Error::new(ErrorKind::Other, msg));
+        }
+
+        let mut ret: Vec<PathBuf> = Vec::new();
+        for name in output.stdout.split(|x| *x == 0) {
+            if name.is_empty() {
+                continue;
+            }
+            let path = dir.join(bytes_to_path(name));
+            // `ls-files --modified` lists unmerged files once per stage
+            if ret.last() != Some(&path) {
+                ret.push(path);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+fn git_error(e: Error) -> Error {
+    if e.kind() == ErrorKind::NotFound {
+        Error::new(
+            ErrorKind::NotFound,
+            "git command is not found, which is required by --git-* options",
+        )
+    } else {
+        e
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// Test
+// ---------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_scope_tracked() {
+        let ret = match GitScope::Tracked.list_files(Path::new("./")) {
+            Ok(x) => x,
+            // git is not installed or the source tree is not a git repository
+            Err(_) => return,
+        };
+
+        assert!(ret.iter().any(|x| x == Path::new("./Cargo.toml")));
+        assert!(ret.iter().any(|x| x == Path::new("./src/lib.rs")));
+        assert!(!ret.iter().any(|x| x.starts_with("./target")));
+
+        let ret = GitScope::Tracked.list_files(Path::new("src/lib.rs")).unwrap();
+        assert_eq!(ret, vec![PathBuf::from("src/lib.rs")]);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::console::Console;
+use crate::matcher::Match;
+use crate::pipeline::{PipelineError, PipelineStage, Statistics};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::path::Path;
+
+// ---------------------------------------------------------------------------------------------------------------------
+// JsonEvent
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// An event of the JSON Lines output.
+///
+/// Field names are a part of the output format, so they should not be changed.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonEvent<'a> {
+    Begin {
+        path: Cow<'a, str>,
+    },
+    Match {
+        path: Cow<'a, str>,
+        /// Line number from 1
+        line_number: usize,
+        /// Byte offset in the line from 1
+        column: usize,
+        /// Byte offsets in the file
+        begin: usize,
+        end: usize,
+        text: Cow<'a, str>,
+        /// The lines including the match without the line break
+        line_text: Cow<'a, str>,
+        submatches: Vec<JsonSubmatch<'a>>,
+    },
+    End {
+        path: Cow<'a, str>,
+        matches: usize,
+    },
+    Summary {
+        elapsed: f64,
+        #[serde(flatten)]
+        statistics: &'a Statistics,
+    },
+    Error {
+        stage: PipelineStage,
+        path: Option<Cow<'a, str>>,
+        kind: String,
+        message: &'a str,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonSubmatch<'a> {
+    /// Index of the capture group from 1
+    pub group: usize,
+    pub begin: usize,
+    pub end: usize,
+    pub text: Cow<'a, str>,
+}
+
+impl<'a> JsonEvent<'a> {
+    /// Build a match event, where `starts` is the line starts of `src` given by `Console::get_line_starts`.
+    pub fn from_match(path: &'a Path, src: &'a [u8], starts: &[usize], m: &Match) -> Self {
+        let first = Console::get_line_index(starts, m.beg);
+        let last = Console::get_line_index(starts, last_byte(m));
+        let (line_beg, _) = Console::get_line_range(src, starts, first);
+        let (_, line_end) = Console::get_line_range(src, starts, last);
+        // Groups not participating in the match are omitted
+        let submatches = m
+            .sub_match
+            .iter()
+            .enumerate()
+            .filter_map(|(i, x)| {
+                x.as_ref().map(|x| JsonSubmatch {
+                    group: i + 1,
+                    begin: x.beg,
+                    end: x.end,
+                    text: String::from_utf8_lossy(&src[x.beg..x.end]),
+                })
+            })
+            .collect();
+
+        JsonEvent::Match {
+            path: path.to_string_lossy(),
+            line_number: first + 1,
+            column: m.beg - line_beg + 1,
+            begin: m.beg,
+            end: m.end,
+            text: String::from_utf8_lossy(&src[m.beg..m.end]),
+            line_text: String::from_utf8_lossy(&src[line_beg..line_end.max(line_beg)]),
+            submatches,
+        }
+    }
+
+    pub fn from_error(e: &'a PipelineError) -> Self {
+        JsonEvent::Error {
+            stage: e.stage,
+            path: e.path.as_ref().map(|x| x.to_string_lossy()),
+            kind: format!("{:?}", e.kind),
+            message: &e.message,
+        }
+    }
+
+    /// Serialize to a line terminated by a line break.
+    pub fn to_line(&self) -> String {
+        let mut ret = serde_json::to_string(self).unwrap_or_default();
+        ret.push('\n');
+        ret
+    }
+}
+
+/// The last byte of a non-empty match, which decides the last line of the match.
+fn last_byte(m: &Match) -> usize {
+    if m.end > m.beg {
+        m.end - 1
+    } else {
+        m.beg
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// Test
+// ---------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_event_match() {
+        let src = b"abc\ndef ghi\r\njkl\n";
+        let starts = Console::get_line_starts(src);
+        let m = Match {
+            beg: 8,
+            end: 11,
+            sub_match: vec![
+                None,
+                Some(Match {
+                    beg: 9,
+                    end: 10,
+                    sub_match: Vec::new(),
+                }),
+            ],
+        };
+        let event = JsonEvent::from_match(Path::new("a.txt"), src, &starts, &m);
+        assert_eq!(
+            event.to_line(),
+            concat!(
+                r#"{"type":"match","path":"a.txt","line_number":2,"column":5,"begin":8,"end":11,"text":"ghi","#,
+                r#""line_text":"def ghi","submatches":[{"group":2,"begin":9,"end":10,"text":"h"}]}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn json_event_summary() {
+        let statistics = Statistics {
+            files_searched: 2,
+            matches: 3,
+            ..Default::default()
+        };
+        let event = JsonEvent::Summary {
+            elapsed: 0.5,
+            statistics: &statistics,
+        };
+        let line = event.to_line();
+        assert!(line.starts_with(r#"{"type":"summary","elapsed":0.5,"files_walked":0,"files_searched":2,"#));
+        assert!(line.contains(r#""matches":3"#));
+    }
+}
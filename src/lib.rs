@@ -1,8 +1,12 @@
 #[macro_use]
 pub mod util;
 pub mod console;
+pub mod format;
+pub mod git;
 pub mod ignore;
+pub mod json;
 pub mod matcher;
+pub mod pager;
 pub mod pipeline;
 pub mod pipeline_finder;
 pub mod pipeline_matcher;
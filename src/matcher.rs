@@ -1,9 +1,13 @@
-use crossbeam::channel::unbounded;
-use regex::RegexBuilder;
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use crossbeam::select;
+use regex::bytes::{Regex, RegexBuilder};
+use regex_syntax::hir::{Class, Hir, HirKind, Look};
+use regex_syntax::ParserBuilder;
 use rlibc::memcmp;
 use std::cmp;
-use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
 use std::str;
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 // ---------------------------------------------------------------------------------------------------------------------
@@ -14,11 +18,113 @@ use std::thread;
 pub struct Match {
     pub beg: usize,
     pub end: usize,
-    pub sub_match: Vec<Match>,
+    /// The capture groups from 1 of a regex match, where a group not participating in the match is `None`
+    pub sub_match: Vec<Option<Match>>,
 }
 
 pub trait Matcher {
     fn search(&self, src: &[u8], pat: &[u8]) -> Vec<Match>;
+
+    /// Search `src` shared with the chunks put into the pool, which can't borrow it because any thread runs them.
+    fn search_shared(&self, src: &SharedSrc, pat: &[u8]) -> Vec<Match> {
+        self.search((**src).as_ref(), pat)
+    }
+}
+
+/// The contents of a file owned by the chunks of it until all of them are searched.
+pub type SharedSrc = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+// ---------------------------------------------------------------------------------------------------------------------
+// ChunkPool
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// A search of a chunk of a large file, which is run by any matcher thread.
+pub struct ChunkJob(Box<dyn FnOnce() + Send + 'static>);
+
+impl ChunkJob {
+    pub fn run(self) {
+        (self.0)()
+    }
+}
+
+/// Chunks of large files shared by all matchers.
+///
+/// A matcher puts the chunks of a large file into the pool, and `PipelineMatcher` threads take them before the next
+/// file, so the chunks are interleaved with small files on the same threads instead of spawning threads per file.
+pub struct ChunkPool {
+    tx: Sender<ChunkJob>,
+    rx: Receiver<ChunkJob>,
+}
+
+impl Default for ChunkPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChunkPool {
+    pub fn new() -> Self {
+        let (tx, rx) = unbounded();
+        ChunkPool { tx, rx }
+    }
+
+    /// The queue of chunks which idle threads take.
+    pub fn jobs(&self) -> &Receiver<ChunkJob> {
+        &self.rx
+    }
+
+    /// Search the chunks of `src` between `bounds` by `search_sub`, and return the results in the order of the chunks.
+    ///
+    /// The first chunk is searched by the current thread, and the others are put into the pool. The current thread
+    /// also runs queued chunks until all of its chunks are searched.
+    fn search<F>(&self, src: &SharedSrc, bounds: &[usize], search_sub: &Arc<F>) -> Vec<Vec<Match>>
+    where
+        F: Fn(&[u8], usize, usize) -> Vec<Match> + Send + Sync + 'static,
+    {
+        let (ret_tx, ret_rx) = unbounded();
+        for (i, x) in bounds.windows(2).enumerate().skip(1) {
+            let (beg, end) = (x[0], x[1]);
+            let src = src.clone();
+            let search_sub = search_sub.clone();
+            let ret_tx = ret_tx.clone();
+            let job = Box::new(move || {
+                let ret = panic::catch_unwind(AssertUnwindSafe(|| search_sub((*src).as_ref(), beg, end)));
+                let _ = ret_tx.send((i, ret));
+            });
+            let _ = self.tx.send(ChunkJob(job));
+        }
+
+        let src = (**src).as_ref();
+        let mut rets = vec![Vec::new(); bounds.len() - 1];
+        let mut panicked = None;
+        match panic::catch_unwind(AssertUnwindSafe(|| search_sub(src, bounds[0], bounds[1]))) {
+            Ok(x) => rets[0] = x,
+            Err(e) => panicked = Some(e),
+        }
+
+        let mut rest = rets.len() - 1;
+        while rest != 0 {
+            select! {
+                recv(ret_rx) -> x => {
+                    match x.unwrap() {
+                        (i, Ok(x)) => rets[i] = x,
+                        (_, Err(e)) => panicked = Some(e),
+                    }
+                    rest -= 1;
+                }
+                recv(self.rx) -> job => {
+                    if let Ok(job) = job {
+                        job.run();
+                    }
+                }
+            }
+        }
+
+        if let Some(e) = panicked {
+            panic::resume_unwind(e);
+        }
+        rets
+    }
 }
 
 // ---------------------------------------------------------------------------------------------------------------------
@@ -115,6 +221,7 @@ impl Matcher for BruteForceMatcher {
 pub struct QuickSearchMatcher {
     pub max_threads: usize,
     pub size_per_thread: usize,
+    pub pool: Option<Arc<ChunkPool>>,
 }
 
 impl Default for QuickSearchMatcher {
@@ -128,10 +235,11 @@ impl QuickSearchMatcher {
         QuickSearchMatcher {
             max_threads: 4,
             size_per_thread: 1024 * 1024,
+            pool: None,
         }
     }
 
-    fn search_sub(&self, src: &[u8], pat: &[u8], qs_table: &[usize; 256], beg: usize, end: usize) -> Vec<Match> {
+    fn search_sub(src: &[u8], pat: &[u8], qs_table: &[usize; 256], beg: usize, end: usize) -> Vec<Match> {
         let src_len = src.len();
         let pat_len = pat.len();
         let mut ret = Vec::new();
@@ -177,7 +285,16 @@ impl QuickSearchMatcher {
 
 impl Matcher for QuickSearchMatcher {
     fn search(&self, src: &[u8], pat: &[u8]) -> Vec<Match> {
-        let src_len = src.len();
+        self.search_src(src, None, pat)
+    }
+
+    fn search_shared(&self, src: &SharedSrc, pat: &[u8]) -> Vec<Match> {
+        self.search_src((**src).as_ref(), Some(src), pat)
+    }
+}
+
+impl QuickSearchMatcher {
+    fn search_src(&self, src: &[u8], shared: Option<&SharedSrc>, pat: &[u8]) -> Vec<Match> {
         let pat_len = pat.len();
 
         let mut qs_table: [usize; 256] = [pat_len + 1; 256];
@@ -187,39 +304,16 @@ impl Matcher for QuickSearchMatcher {
             i += 1;
         }
 
-        let thread_num = cmp::min(src_len / self.size_per_thread + 1, self.max_threads);
-
-        if thread_num == 1 {
-            self.search_sub(src, pat, &qs_table, 0, src_len)
-        } else {
-            let (tx, rx) = unbounded();
-            thread::scope(|s| {
-                for i in 0..thread_num {
-                    let tx = tx.clone();
-                    let beg = src_len * i / thread_num;
-                    let end = src_len * (i + 1) / thread_num;
-                    s.spawn(move || {
-                        let tmp = self.search_sub(src, pat, &qs_table, beg, end);
-                        let _ = tx.send((i, tmp));
-                    });
-                }
-            });
-
-            let mut rets = HashMap::new();
-            for _ in 0..thread_num {
-                let (i, tmp) = rx.recv().unwrap();
-                rets.insert(i, tmp);
-            }
-
-            let mut ret = Vec::new();
-            for i in 0..thread_num {
-                let tmp = rets.get(&i).unwrap();
-                for t in tmp {
-                    ret.push(t.clone());
-                }
-            }
-            ret
-        }
+        let pat = pat.to_vec();
+        MatcherUtil::search_chunked(
+            src,
+            shared,
+            self.max_threads,
+            self.size_per_thread,
+            self.pool.as_deref(),
+            false,
+            move |src, beg, end| QuickSearchMatcher::search_sub(src, &pat, &qs_table, beg, end),
+        )
     }
 }
 
@@ -230,6 +324,7 @@ impl Matcher for QuickSearchMatcher {
 pub struct TbmMatcher {
     pub max_threads: usize,
     pub size_per_thread: usize,
+    pub pool: Option<Arc<ChunkPool>>,
 }
 
 impl Default for TbmMatcher {
@@ -243,18 +338,11 @@ impl TbmMatcher {
         TbmMatcher {
             max_threads: 4,
             size_per_thread: 1024 * 1024,
+            pool: None,
         }
     }
 
-    fn search_sub(
-        &self,
-        src: &[u8],
-        pat: &[u8],
-        qs_table: &[usize; 256],
-        md2: usize,
-        beg: usize,
-        end: usize,
-    ) -> Vec<Match> {
+    fn search_sub(src: &[u8], pat: &[u8], qs_table: &[usize; 256], md2: usize, beg: usize, end: usize) -> Vec<Match> {
         let src_len = src.len();
         let pat_len = pat.len();
         let mut ret = Vec::new();
@@ -262,6 +350,8 @@ impl TbmMatcher {
         let src_ptr = src.as_ptr();
         let pat_ptr = pat.as_ptr();
 
+        // `i` points the last byte of the candidate, and the candidate begins before `end`
+        let end = cmp::min(end + pat_len - 1, src_len);
         let mut i = beg + pat_len - 1;
         'outer: while i < end {
             let mut k = qs_table[src[i] as usize];
@@ -304,7 +394,16 @@ impl TbmMatcher {
 
 impl Matcher for TbmMatcher {
     fn search(&self, src: &[u8], pat: &[u8]) -> Vec<Match> {
-        let src_len = src.len();
+        self.search_src(src, None, pat)
+    }
+
+    fn search_shared(&self, src: &SharedSrc, pat: &[u8]) -> Vec<Match> {
+        self.search_src((**src).as_ref(), Some(src), pat)
+    }
+}
+
+impl TbmMatcher {
+    fn search_src(&self, src: &[u8], shared: Option<&SharedSrc>, pat: &[u8]) -> Vec<Match> {
         let pat_len = pat.len();
 
         let mut qs_table: [usize; 256] = [pat_len; 256];
@@ -322,39 +421,16 @@ impl Matcher for TbmMatcher {
         }
         let md2 = (pe - p) as usize;
 
-        let thread_num = cmp::min(src_len / self.size_per_thread + 1, self.max_threads);
-
-        if thread_num == 1 {
-            self.search_sub(src, pat, &qs_table, md2, 0, src_len)
-        } else {
-            let (tx, rx) = unbounded();
-            thread::scope(|s| {
-                for i in 0..thread_num {
-                    let tx = tx.clone();
-                    let beg = src_len * i / thread_num;
-                    let end = src_len * (i + 1) / thread_num;
-                    s.spawn(move || {
-                        let tmp = self.search_sub(src, pat, &qs_table, md2, beg, end);
-                        let _ = tx.send((i, tmp));
-                    });
-                }
-            });
-
-            let mut rets = HashMap::new();
-            for _ in 0..thread_num {
-                let (i, tmp) = rx.recv().unwrap();
-                rets.insert(i, tmp);
-            }
-
-            let mut ret = Vec::new();
-            for i in 0..thread_num {
-                let tmp = rets.get(&i).unwrap();
-                for t in tmp {
-                    ret.push(t.clone());
-                }
-            }
-            ret
-        }
+        let pat = pat.to_vec();
+        MatcherUtil::search_chunked(
+            src,
+            shared,
+            self.max_threads,
+            self.size_per_thread,
+            self.pool.as_deref(),
+            false,
+            move |src, beg, end| TbmMatcher::search_sub(src, &pat, &qs_table, md2, beg, end),
+        )
     }
 }
 
@@ -365,6 +441,7 @@ impl Matcher for TbmMatcher {
 pub struct FjsMatcher {
     pub max_threads: usize,
     pub size_per_thread: usize,
+    pub pool: Option<Arc<ChunkPool>>,
     pub use_sse: bool,
 }
 
@@ -379,13 +456,13 @@ impl FjsMatcher {
         FjsMatcher {
             max_threads: 4,
             size_per_thread: 1024 * 1024,
+            pool: None,
             use_sse: false,
         }
     }
 
     #[allow(unused_variables)]
     fn search_sub(
-        &self,
         src: &[u8],
         pat: &[u8],
         betap: &[isize; 101],
@@ -464,7 +541,16 @@ impl FjsMatcher {
 
 impl Matcher for FjsMatcher {
     fn search(&self, src: &[u8], pat: &[u8]) -> Vec<Match> {
-        let src_len = src.len();
+        self.search_src(src, None, pat)
+    }
+
+    fn search_shared(&self, src: &SharedSrc, pat: &[u8]) -> Vec<Match> {
+        self.search_src((**src).as_ref(), Some(src), pat)
+    }
+}
+
+impl FjsMatcher {
+    fn search_src(&self, src: &[u8], shared: Option<&SharedSrc>, pat: &[u8]) -> Vec<Match> {
         let pat_len = pat.len();
 
         let mut betap: [isize; 101] = [-1; 101];
@@ -489,39 +575,16 @@ impl Matcher for FjsMatcher {
             delta[pat[i] as usize] = pat_len - i;
         }
 
-        let thread_num = cmp::min(src_len / self.size_per_thread + 1, self.max_threads);
-
-        if thread_num == 1 {
-            self.search_sub(src, pat, &betap, &delta, 0, src_len)
-        } else {
-            let (tx, rx) = unbounded();
-            thread::scope(|s| {
-                for i in 0..thread_num {
-                    let tx = tx.clone();
-                    let beg = src_len * i / thread_num;
-                    let end = src_len * (i + 1) / thread_num;
-                    s.spawn(move || {
-                        let tmp = self.search_sub(src, pat, &betap, &delta, beg, end);
-                        let _ = tx.send((i, tmp));
-                    });
-                }
-            });
-
-            let mut rets = HashMap::new();
-            for _ in 0..thread_num {
-                let (i, tmp) = rx.recv().unwrap();
-                rets.insert(i, tmp);
-            }
-
-            let mut ret = Vec::new();
-            for i in 0..thread_num {
-                let tmp = rets.get(&i).unwrap();
-                for t in tmp {
-                    ret.push(t.clone());
-                }
-            }
-            ret
-        }
+        let pat = pat.to_vec();
+        MatcherUtil::search_chunked(
+            src,
+            shared,
+            self.max_threads,
+            self.size_per_thread,
+            self.pool.as_deref(),
+            false,
+            move |src, beg, end| FjsMatcher::search_sub(src, &pat, &betap, &delta, beg, end),
+        )
     }
 }
 
@@ -529,11 +592,81 @@ impl Matcher for FjsMatcher {
 // RegexMatcher
 // ---------------------------------------------------------------------------------------------------------------------
 
-pub struct RegexMatcher;
+pub struct RegexMatcher {
+    pub max_threads: usize,
+    pub size_per_thread: usize,
+    pub pool: Option<Arc<ChunkPool>>,
+    /// Fill `sub_match` with the capture groups, which is slower than finding the whole match only
+    pub capture_groups: bool,
+    cache: Mutex<Option<RegexCache>>,
+}
+
+struct RegexCache {
+    pat: Vec<u8>,
+    compiled: Option<(Regex, bool)>,
+}
 
 impl RegexMatcher {
     pub fn new() -> Self {
-        RegexMatcher
+        RegexMatcher {
+            max_threads: 4,
+            size_per_thread: 1024 * 1024,
+            pool: None,
+            capture_groups: false,
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Get the number of capture groups of `pat` except the whole match, or `None` if `pat` is not a valid regex.
+    pub fn group_count(pat: &[u8]) -> Option<usize> {
+        let pat_str = str::from_utf8(pat).ok()?;
+        let re = RegexBuilder::new(pat_str).multi_line(true).build().ok()?;
+        Some(re.captures_len() - 1)
+    }
+
+    /// Compile `pat` at the first search, and check whether the regex can be searched by line-aligned chunks.
+    fn compile(&self, pat: &[u8]) -> Option<(Regex, bool)> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(ref x) = *cache {
+            if x.pat == pat {
+                return x.compiled.clone();
+            }
+        }
+
+        let re = str::from_utf8(pat).ok().and_then(|pat_str| {
+            let re = RegexBuilder::new(pat_str).multi_line(true).build().ok()?;
+            let chunkable = match ParserBuilder::new().multi_line(true).build().parse(pat_str) {
+                Ok(hir) => RegexMatcher::is_chunkable(&hir),
+                Err(_) => false,
+            };
+            Some((re, chunkable))
+        });
+        *cache = Some(RegexCache {
+            pat: pat.to_vec(),
+            compiled: re.clone(),
+        });
+        re
+    }
+
+    /// A regex can be searched by line-aligned chunks if each match is a non-empty part of a line.
+    fn is_chunkable(hir: &Hir) -> bool {
+        let props = hir.properties();
+        props.minimum_len().is_some_and(|x| x > 0)
+            && !props.look_set().contains(Look::Start)
+            && !props.look_set().contains(Look::End)
+            && !RegexMatcher::match_newline(hir)
+    }
+
+    fn match_newline(hir: &Hir) -> bool {
+        match hir.kind() {
+            HirKind::Empty | HirKind::Look(_) => false,
+            HirKind::Literal(x) => x.0.contains(&b'\n'),
+            HirKind::Class(Class::Unicode(x)) => x.ranges().iter().any(|r| r.start() <= '\n' && '\n' <= r.end()),
+            HirKind::Class(Class::Bytes(x)) => x.ranges().iter().any(|r| r.start() <= b'\n' && b'\n' <= r.end()),
+            HirKind::Repetition(x) => RegexMatcher::match_newline(&x.sub),
+            HirKind::Capture(x) => RegexMatcher::match_newline(&x.sub),
+            HirKind::Concat(x) | HirKind::Alternation(x) => x.iter().any(RegexMatcher::match_newline),
+        }
     }
 }
 
@@ -545,32 +678,74 @@ impl Default for RegexMatcher {
 
 impl Matcher for RegexMatcher {
     fn search(&self, src: &[u8], pat: &[u8]) -> Vec<Match> {
-        let pat_str = match str::from_utf8(pat) {
-            Ok(x) => x,
-            Err(_) => return Vec::new(),
-        };
+        self.search_src(src, None, pat)
+    }
 
-        let src_str = match str::from_utf8(src) {
-            Ok(x) => x,
-            Err(_) => return Vec::new(),
-        };
+    fn search_shared(&self, src: &SharedSrc, pat: &[u8]) -> Vec<Match> {
+        self.search_src((**src).as_ref(), Some(src), pat)
+    }
+}
 
-        let re = match RegexBuilder::new(pat_str).multi_line(true).build() {
-            Ok(x) => x,
-            Err(_) => return Vec::new(),
+impl RegexMatcher {
+    fn search_src(&self, src: &[u8], shared: Option<&SharedSrc>, pat: &[u8]) -> Vec<Match> {
+        let (re, chunkable) = match self.compile(pat) {
+            Some(x) => x,
+            None => return Vec::new(),
         };
 
-        let result = re.find_iter(src_str);
+        // Chunks are searched as bytes, which is same as str because the whole contents is checked here
+        if str::from_utf8(src).is_err() {
+            return Vec::new();
+        }
 
-        let mut ret = Vec::new();
-        for r in result {
-            ret.push(Match {
-                beg: r.start(),
-                end: r.end(),
-                sub_match: Vec::new(),
-            });
+        let capture_groups = self.capture_groups;
+        let search_sub = move |src: &[u8], beg: usize, end: usize| {
+            let mut ret = Vec::new();
+            if capture_groups {
+                for c in re.captures_iter(&src[beg..end]) {
+                    let r = c.get(0).unwrap();
+                    let sub_match = c
+                        .iter()
+                        .skip(1)
+                        .map(|x| {
+                            x.map(|x| Match {
+                                beg: beg + x.start(),
+                                end: beg + x.end(),
+                                sub_match: Vec::new(),
+                            })
+                        })
+                        .collect();
+                    ret.push(Match {
+                        beg: beg + r.start(),
+                        end: beg + r.end(),
+                        sub_match,
+                    });
+                }
+            } else {
+                for r in re.find_iter(&src[beg..end]) {
+                    ret.push(Match {
+                        beg: beg + r.start(),
+                        end: beg + r.end(),
+                        sub_match: Vec::new(),
+                    });
+                }
+            }
+            ret
+        };
+
+        if chunkable {
+            MatcherUtil::search_chunked(
+                src,
+                shared,
+                self.max_threads,
+                self.size_per_thread,
+                self.pool.as_deref(),
+                true,
+                search_sub,
+            )
+        } else {
+            search_sub(src, 0, src.len())
         }
-        ret
     }
 }
 
@@ -581,6 +756,77 @@ impl Matcher for RegexMatcher {
 struct MatcherUtil;
 
 impl MatcherUtil {
+    /// Split `src` into chunks and search them in parallel by `search_sub`, on the threads of `pool` if it is given
+    /// and `src` is `shared`, otherwise on scoped threads.
+    ///
+    /// `search_sub(src, beg, end)` returns the matches which begin in `beg..end`, so a match across a boundary is found
+    /// by the former chunk. If `line_aligned` is set, each boundary is moved to the next line head.
+    fn search_chunked<F>(
+        src: &[u8],
+        shared: Option<&SharedSrc>,
+        max_threads: usize,
+        size_per_thread: usize,
+        pool: Option<&ChunkPool>,
+        line_aligned: bool,
+        search_sub: F,
+    ) -> Vec<Match>
+    where
+        F: Fn(&[u8], usize, usize) -> Vec<Match> + Send + Sync + 'static,
+    {
+        let src_len = src.len();
+        let thread_num = cmp::min(src_len / cmp::max(size_per_thread, 1) + 1, cmp::max(max_threads, 1));
+
+        if thread_num == 1 {
+            return search_sub(src, 0, src_len);
+        }
+
+        let mut bounds = vec![0];
+        for i in 1..thread_num {
+            let mut pos = cmp::max(src_len * i / thread_num, *bounds.last().unwrap());
+            if line_aligned {
+                pos = match src[pos..].iter().position(|x| *x == b'\n') {
+                    Some(x) => pos + x + 1,
+                    None => src_len,
+                };
+            }
+            bounds.push(pos);
+        }
+        bounds.push(src_len);
+
+        let search_sub = Arc::new(search_sub);
+        let rets: Vec<Vec<Match>> = match (pool, shared) {
+            (Some(pool), Some(shared)) => pool.search(shared, &bounds, &search_sub),
+            _ => thread::scope(|s| {
+                let search_sub = &search_sub;
+                let handles: Vec<_> = bounds[1..]
+                    .windows(2)
+                    .map(|x| {
+                        let (beg, end) = (x[0], x[1]);
+                        s.spawn(move || search_sub(src, beg, end))
+                    })
+                    .collect();
+
+                let mut rets = vec![search_sub(src, bounds[0], bounds[1])];
+                for h in handles {
+                    rets.push(h.join().unwrap());
+                }
+                rets
+            }),
+        };
+
+        let mut ret: Vec<Match> = Vec::new();
+        for (i, tmp) in rets.into_iter().enumerate() {
+            let prev_end = ret.last().map_or(0, |x| x.end);
+            if tmp.first().is_some_and(|x| x.beg < prev_end) {
+                // The chunk begins inside a match across the boundary, so it is searched again after the match
+                ret.append(&mut search_sub(src, prev_end, bounds[i + 1]));
+            } else {
+                ret.extend(tmp);
+            }
+        }
+        ret
+    }
+
     fn check_char_boundary(src: &[u8], pos: usize) -> bool {
         let mut pos_ascii = if pos == 0 { 0 } else { pos - 1 };
         while pos_ascii > 0 {
@@ -633,6 +879,7 @@ impl MatcherUtil {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
 
     fn test_matcher<T: Matcher>(m: &T) {
         let src = "abcabcaaaaabc".to_string().into_bytes();
@@ -711,4 +958,117 @@ mod tests {
         let matcher = RegexMatcher::new();
         test_matcher(&matcher);
     }
+
+    #[test]
+    fn test_regex_capture_groups() {
+        let mut matcher = RegexMatcher::new();
+        matcher.capture_groups = true;
+        let ret = matcher.search(b"ab ac", b"a(b)|a(c)");
+        assert_eq!(ret.len(), 2);
+        assert_eq!(
+            ret[0]
+                .sub_match
+                .iter()
+                .map(|x| x.as_ref().map(|x| (x.beg, x.end)))
+                .collect::<Vec<_>>(),
+            vec![Some((1, 2)), None]
+        );
+        assert_eq!(
+            ret[1]
+                .sub_match
+                .iter()
+                .map(|x| x.as_ref().map(|x| (x.beg, x.end)))
+                .collect::<Vec<_>>(),
+            vec![None, Some((4, 5))]
+        );
+
+        // An empty group participating in the match is kept
+        let ret = matcher.search(b"ab", b"a(x*)b");
+        assert_eq!(
+            ret[0]
+                .sub_match
+                .iter()
+                .map(|x| x.as_ref().map(|x| (x.beg, x.end)))
+                .collect::<Vec<_>>(),
+            vec![Some((1, 1))]
+        );
+    }
+
+    #[test]
+    fn test_regex_group_count() {
+        assert_eq!(RegexMatcher::group_count(b"abc"), Some(0));
+        assert_eq!(RegexMatcher::group_count(b"a(b)|a(c)"), Some(2));
+        assert_eq!(RegexMatcher::group_count(b"a(?:b)(?P<x>c)"), Some(1));
+        assert_eq!(RegexMatcher::group_count(b"a(b"), None);
+    }
+
+    fn test_chunked<T: Matcher>(m: &T, src: &[u8], pat: &[u8], single: &T) {
+        let shared: SharedSrc = Arc::new(src.to_vec());
+        let ret = m.search_shared(&shared, pat);
+        // Without the shared contents, the chunks are searched by scoped threads
+        assert_eq!(
+            m.search(src, pat).iter().map(|x| (x.beg, x.end)).collect::<Vec<_>>(),
+            ret.iter().map(|x| (x.beg, x.end)).collect::<Vec<_>>()
+        );
+        let expected = single.search(src, pat);
+        assert!(!expected.is_empty(), "{:?}", str::from_utf8(pat));
+        assert_eq!(
+            ret.iter().map(|x| (x.beg, x.end)).collect::<Vec<_>>(),
+            expected.iter().map(|x| (x.beg, x.end)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_chunked_matcher() {
+        let src = "abcaaaaaaaabcabc\naaaaabcbcabc\nabcaaabc".repeat(10).into_bytes();
+        // Threads which take chunks as `PipelineMatcher` does, though the searching thread may take all of them
+        let pool = Arc::new(ChunkPool::new());
+        for _ in 0..2 {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                while let Ok(job) = pool.jobs().recv_timeout(Duration::from_secs(1)) {
+                    job.run();
+                }
+            });
+        }
+
+        for pat in ["a", "aa", "abc", "cab"] {
+            let mut m = QuickSearchMatcher::new();
+            m.size_per_thread = 7;
+            m.pool = Some(pool.clone());
+            let mut single = QuickSearchMatcher::new();
+            single.max_threads = 1;
+            test_chunked(&m, &src, pat.as_bytes(), &single);
+
+            let mut m = TbmMatcher::new();
+            m.size_per_thread = 7;
+            m.pool = Some(pool.clone());
+            let mut single = TbmMatcher::new();
+            single.max_threads = 1;
+            test_chunked(&m, &src, pat.as_bytes(), &single);
+        }
+
+        for pat in ["a+", "b.a", "^abc", "abc$", "\\bab"] {
+            let mut m = RegexMatcher::new();
+            m.size_per_thread = 7;
+            m.pool = Some(pool.clone());
+            let mut single = RegexMatcher::new();
+            single.max_threads = 1;
+            test_chunked(&m, &src, pat.as_bytes(), &single);
+        }
+
+        assert!(pool.jobs().is_empty());
+    }
+
+    #[test]
+    fn test_regex_chunkable() {
+        let parse = |x: &str| ParserBuilder::new().multi_line(true).build().parse(x).unwrap();
+        assert!(RegexMatcher::is_chunkable(&parse("abc")));
+        assert!(RegexMatcher::is_chunkable(&parse("^a.*c$")));
+        assert!(!RegexMatcher::is_chunkable(&parse("a\\sb")));
+        assert!(!RegexMatcher::is_chunkable(&parse("[^a]")));
+        assert!(!RegexMatcher::is_chunkable(&parse("^$")));
+        assert!(!RegexMatcher::is_chunkable(&parse("\\Aabc")));
+        assert!(!RegexMatcher::is_chunkable(&parse("(?s)a.c")));
+    }
 }
@@ -0,0 +1,102 @@
+use crate::console::Console;
+use std::env;
+use std::io::{self, Write};
+use std::process::{Child, Command, Stdio};
+
+// ---------------------------------------------------------------------------------------------------------------------
+// Pager
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// A pager which stdout is redirected to, so that the output written through `Console` is shown by it.
+pub struct Pager {
+    child: Child,
+    /// The terminal which stdout was before the redirection
+    #[cfg(not(windows))]
+    stdout: libc::c_int,
+}
+
+impl Pager {
+    /// Get the command line of the pager by `AMBS_PAGER` or `PAGER`, where an empty value disables the pager.
+    pub fn command() -> Option<String> {
+        Pager::command_from(env::var("AMBS_PAGER").ok(), env::var("PAGER").ok())
+    }
+
+    fn command_from(ambs_pager: Option<String>, pager: Option<String>) -> Option<String> {
+        let command = ambs_pager.or(pager).unwrap_or_else(|| String::from("less"));
+        if command.trim().is_empty() {
+            None
+        } else {
+            Some(command)
+        }
+    }
+
+    /// Start the pager, and redirect stdout to it.
+    /// `None` is returned if the pager can't be started, and then stdout is kept as is.
+    #[cfg(not(windows))]
+    pub fn start() -> Option<Pager> {
+        use std::os::unix::io::AsRawFd;
+
+        // The command line is run by the shell as git does, so that quotes and variables in it work
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(Pager::command()?).stdin(Stdio::piped());
+        // Quit if the output fits in a screen, keep colors, and keep the output on the screen after quit
+        if env::var_os("LESS").is_none() {
+            command.env("LESS", "FRX");
+        }
+        let mut child = command.spawn().ok()?;
+        let stdin = child.stdin.take()?;
+
+        let _ = io::stdout().flush();
+        let stdout = unsafe { libc::dup(1) };
+        if stdout < 0 || unsafe { libc::dup2(stdin.as_raw_fd(), 1) } < 0 {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        Some(Pager { child, stdout })
+    }
+
+    #[cfg(windows)]
+    pub fn start() -> Option<Pager> {
+        None
+    }
+
+    /// Close the output to the pager, and wait until the user quits it.
+    pub fn finish(mut self) {
+        let _ = io::stdout().flush();
+        // Restoring stdout closes the last writer of the pipe, so the pager gets EOF.
+        // If the pager has quit, stdout is kept to it, so that the output left in the buffer is not shown on the terminal.
+        #[cfg(not(windows))]
+        unsafe {
+            if !Console::is_closed() {
+                libc::dup2(self.stdout, 1);
+            }
+            libc::close(self.stdout);
+        }
+        let _ = self.child.wait();
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// Test
+// ---------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        let some = |x: &str| Some(String::from(x));
+        assert_eq!(
+            Pager::command_from(some("less -R '+/a b'"), some("more")),
+            some("less -R '+/a b'")
+        );
+        assert_eq!(Pager::command_from(None, some("more")), some("more"));
+        assert_eq!(Pager::command_from(None, None), some("less"));
+        // An empty value disables the pager, even if the other is set
+        assert_eq!(Pager::command_from(some(""), some("more")), None);
+        assert_eq!(Pager::command_from(some(" "), None), None);
+        assert_eq!(Pager::command_from(None, some("")), None);
+    }
+}
@@ -1,14 +1,23 @@
-use crossbeam::channel::{Receiver, Sender};
-use std::time::Duration;
+use crate::util::decode_error;
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+use serde::Serialize;
+use std::cmp;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 pub enum PipelineInfo<T> {
     SeqBeg(usize),
     SeqDat(usize, T),
     SeqEnd(usize),
-    MsgInfo(usize, String),
+    MsgInfo(usize, PipelineNotice),
     MsgDebug(usize, String),
-    MsgErr(usize, String),
+    MsgErr(usize, PipelineError),
     MsgTime(usize, Duration, Duration),
+    MsgProgress(usize, Progress),
+    MsgStat(usize, Statistics),
 }
 
 pub trait Pipeline<T, U> {
@@ -22,3 +31,300 @@ pub trait PipelineFork<T, U> {
 pub trait PipelineJoin<T, U> {
     fn setup(&mut self, id: usize, rx: Vec<Receiver<PipelineInfo<T>>>, tx: Sender<PipelineInfo<U>>);
 }
+
+/// Create a channel between stages, which is unbounded if `capacity` is 0.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    if capacity == 0 {
+        unbounded()
+    } else {
+        bounded(capacity)
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// PipelineNotice / PipelineError
+// ---------------------------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PipelineStage {
+    Main,
+    Finder,
+    Matcher,
+    Sorter,
+    Printer,
+    Replacer,
+}
+
+impl fmt::Display for PipelineStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            PipelineStage::Main => "main",
+            PipelineStage::Finder => "finder",
+            PipelineStage::Matcher => "matcher",
+            PipelineStage::Sorter => "sorter",
+            PipelineStage::Printer => "printer",
+            PipelineStage::Replacer => "replacer",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The file is detected as binary
+    Binary,
+    /// The path is a file or directory of a version control system
+    Vcs,
+    /// The path is ignored by .gitignore
+    Gitignore,
+    /// The path is neither a regular file nor a directory
+    NotFile,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            SkipReason::Binary => "binary",
+            SkipReason::Vcs => "vcs file",
+            SkipReason::Gitignore => ".gitignore",
+            SkipReason::NotFile => "not a file",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// An informational event of a stage, which is rendered as text by the CLI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipelineNotice {
+    Skipped { path: PathBuf, reason: SkipReason },
+    ParentGitignore(PathBuf),
+}
+
+impl fmt::Display for PipelineNotice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PipelineNotice::Skipped { path, reason } => {
+                write!(f, "Skip {:<12}: {:?}", format!("({})", reason), path)
+            }
+            PipelineNotice::ParentGitignore(path) => {
+                write!(f, "Found .gitignore at the parent directory: {:?}", path)
+            }
+        }
+    }
+}
+
+/// An error of a stage, which keeps the kind of the underlying `io::Error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineError {
+    pub stage: PipelineStage,
+    pub path: Option<PathBuf>,
+    pub kind: io::ErrorKind,
+    pub message: String,
+}
+
+impl PipelineError {
+    pub fn new(stage: PipelineStage, path: Option<&Path>, e: &io::Error) -> Self {
+        PipelineError {
+            stage,
+            path: path.map(|x| x.to_path_buf()),
+            kind: e.kind(),
+            message: decode_error(e),
+        }
+    }
+
+    pub fn with_message(stage: PipelineStage, path: Option<&Path>, kind: io::ErrorKind, message: &str) -> Self {
+        PipelineError {
+            stage,
+            path: path.map(|x| x.to_path_buf()),
+            kind,
+            message: String::from(message),
+        }
+    }
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.path {
+            Some(ref path) => write!(f, "Error: {} @ {:?}", self.message, path),
+            None => write!(f, "Error: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// Progress
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// Cumulative counters of a stage for the progress display.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    pub files_found: usize,
+    pub files_searched: usize,
+    pub bytes_scanned: u64,
+    pub matches: usize,
+    pub current: Option<PathBuf>,
+    last_sent: Option<Instant>,
+}
+
+impl Progress {
+    pub const INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Return a copy to be sent if `INTERVAL` has passed since the last one, or if `force` is set.
+    pub fn throttle(&mut self, force: bool) -> Option<Progress> {
+        let now = Instant::now();
+        if !force
+            && self
+                .last_sent
+                .is_some_and(|x| now.duration_since(x) < Progress::INTERVAL)
+        {
+            return None;
+        }
+        self.last_sent = Some(now);
+        Some(self.clone())
+    }
+
+    pub fn add(&mut self, other: &Progress) {
+        self.files_found += other.files_found;
+        self.files_searched += other.files_searched;
+        self.bytes_scanned += other.bytes_scanned;
+        self.matches += other.matches;
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut bytes = self.bytes_scanned as f64;
+        let mut unit = 0;
+        while bytes >= 1024.0 && unit < 4 {
+            bytes /= 1024.0;
+            unit += 1;
+        }
+        // The counter of the finder passes through the queue of matchers, so it may lag behind
+        write!(
+            f,
+            "Found: {} files, Searched: {} files ( {:.1}{} ), Matches: {}",
+            cmp::max(self.files_found, self.files_searched),
+            self.files_searched,
+            bytes,
+            ["B", "KiB", "MiB", "GiB", "TiB"][unit],
+            self.matches
+        )?;
+
+        if let Some(ref path) = self.current {
+            // Keep the tail of the path so that the display fits in a line
+            let path = path.to_string_lossy();
+            let chars: Vec<char> = path.chars().collect();
+            if chars.len() > 48 {
+                write!(f, " @ ...{}", chars[chars.len() - 45..].iter().collect::<String>())?;
+            } else {
+                write!(f, " @ {}", path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// Statistics
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// Numbers of paths skipped by each reason.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SkipCount {
+    pub binary: usize,
+    pub vcs: usize,
+    pub gitignore: usize,
+    pub not_file: usize,
+}
+
+impl SkipCount {
+    pub fn total(&self) -> usize {
+        self.binary + self.vcs + self.gitignore + self.not_file
+    }
+}
+
+/// Counters of a stage, which are sent at the end and summed for the statistics output.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Statistics {
+    /// Files found by the finder to be searched
+    pub files_walked: usize,
+    /// Files searched, excluding skipped ones and errors
+    pub files_searched: usize,
+    pub files_skipped: SkipCount,
+    pub bytes_read: u64,
+    /// Files read through mmap
+    pub mmap_files: usize,
+    /// Files read into a buffer
+    pub read_files: usize,
+    pub matches: usize,
+    /// Errors of all stages, which are counted by the receiver of `MsgErr`
+    pub errors: usize,
+}
+
+impl Statistics {
+    pub fn skip(&mut self, reason: SkipReason) {
+        match reason {
+            SkipReason::Binary => self.files_skipped.binary += 1,
+            SkipReason::Vcs => self.files_skipped.vcs += 1,
+            SkipReason::Gitignore => self.files_skipped.gitignore += 1,
+            SkipReason::NotFile => self.files_skipped.not_file += 1,
+        }
+    }
+
+    pub fn add(&mut self, other: &Statistics) {
+        self.files_walked += other.files_walked;
+        self.files_searched += other.files_searched;
+        self.files_skipped.binary += other.files_skipped.binary;
+        self.files_skipped.vcs += other.files_skipped.vcs;
+        self.files_skipped.gitignore += other.files_skipped.gitignore;
+        self.files_skipped.not_file += other.files_skipped.not_file;
+        self.bytes_read += other.bytes_read;
+        self.mmap_files += other.mmap_files;
+        self.read_files += other.read_files;
+        self.matches += other.matches;
+        self.errors += other.errors;
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// SeqGate
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// The next sequence number expected by the join stage.
+///
+/// A fork stage waits before sending a sequence number which is `window` or more ahead, so the join stage buffers at
+/// most `window` out-of-order results.
+pub struct SeqGate {
+    next: Mutex<usize>,
+    cond: Condvar,
+    window: usize,
+}
+
+impl SeqGate {
+    pub fn new(window: usize) -> Self {
+        SeqGate {
+            next: Mutex::new(0),
+            cond: Condvar::new(),
+            window,
+        }
+    }
+
+    pub fn next(&self) -> usize {
+        *self.next.lock().unwrap()
+    }
+
+    pub fn advance(&self, next: usize) {
+        *self.next.lock().unwrap() = next;
+        self.cond.notify_all();
+    }
+
+    pub fn wait(&self, seq: usize) {
+        let mut next = self.next.lock().unwrap();
+        while seq >= *next + self.window {
+            next = self.cond.wait(next).unwrap();
+        }
+    }
+}
@@ -1,8 +1,16 @@
+use crate::git::GitScope;
 use crate::ignore::{Gitignore, Ignore, IgnoreVcs};
-use crate::pipeline::{PipelineFork, PipelineInfo};
+use crate::pipeline::{
+    Pipeline, PipelineError, PipelineInfo, PipelineNotice, PipelineStage, Progress, SeqGate, SkipReason, Statistics,
+};
 use crossbeam::channel::{Receiver, Sender};
+use glob::{MatchOptions, Pattern};
+use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 // ---------------------------------------------------------------------------------------------------------------------
@@ -12,6 +20,89 @@ use std::time::{Duration, Instant};
 #[derive(Debug, Clone)]
 pub struct PathInfo {
     pub path: PathBuf,
+    pub size: u64,
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// PendingPath
+// ---------------------------------------------------------------------------------------------------------------------
+
+struct PendingPath {
+    seq: usize,
+    info: PathInfo,
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// GlobFilter
+// ---------------------------------------------------------------------------------------------------------------------
+
+struct GlobFilter {
+    base: PathBuf,
+    pattern: Pattern,
+    depth: Option<usize>,
+}
+
+impl GlobFilter {
+    const OPTIONS: MatchOptions = MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+
+    /// Split `path` into the literal base directory and the glob pattern below it.
+    /// `None` is returned if `path` exists or has no wildcard.
+    fn new(path: &Path) -> Option<Result<GlobFilter, PipelineError>> {
+        if path.exists() {
+            return None;
+        }
+
+        let mut base = PathBuf::new();
+        let mut rest = Vec::new();
+        for c in path.components() {
+            let c = c.as_os_str().to_string_lossy();
+            if rest.is_empty() && !c.contains(['*', '?', '[']) {
+                base.push(c.as_ref());
+            } else {
+                rest.push(c.into_owned());
+            }
+        }
+        if rest.is_empty() {
+            return None;
+        }
+
+        let depth = if rest.iter().any(|x| x.contains("**")) {
+            None
+        } else {
+            Some(rest.len())
+        };
+        if base.as_os_str().is_empty() {
+            base.push(".");
+        }
+
+        Some(match Pattern::new(&rest.join("/")) {
+            Ok(pattern) => Ok(GlobFilter { base, pattern, depth }),
+            Err(e) => Err(PipelineError::with_message(
+                PipelineStage::Finder,
+                Some(path),
+                io::ErrorKind::InvalidInput,
+                e.msg,
+            )),
+        })
+    }
+
+    /// A path matches if the path itself or one of its parent directories matches the pattern.
+    /// Directories are also kept while they can contain a matched path.
+    fn is_match(&self, path: &Path, is_dir: bool) -> bool {
+        let rel = match path.strip_prefix(&self.base) {
+            Ok(x) => x,
+            Err(_) => return true,
+        };
+        let matched = rel
+            .ancestors()
+            .filter(|x| !x.as_os_str().is_empty())
+            .any(|x| self.pattern.matches_path_with(x, GlobFilter::OPTIONS));
+        matched || is_dir && self.depth.map_or(true, |x| rel.components().count() < x)
+    }
 }
 
 // ---------------------------------------------------------------------------------------------------------------------
@@ -27,14 +118,27 @@ pub struct PipelineFinder {
     pub skip_ambignore: bool,
     pub print_skipped: bool,
     pub find_parent_ignore: bool,
-    pub infos: Vec<String>,
-    pub errors: Vec<String>,
+    pub git_scope: Option<GitScope>,
+    pub from_list: bool,
+    pub dispatch_window: usize,
+    pub matcher_num: usize,
+    pub sort_path: bool,
+    pub sort_reverse: bool,
+    pub print_progress: bool,
+    pub gate: Option<Arc<SeqGate>>,
+    pub infos: Vec<PipelineNotice>,
+    pub errors: Vec<PipelineError>,
+    pub statistics: Statistics,
     time_beg: Instant,
     time_bsy: Duration,
+    id: usize,
     seq_no: usize,
-    current_tx: usize,
+    progress: Progress,
+    pending: Vec<PendingPath>,
     ignore_vcs: IgnoreVcs,
     ignore_git: Vec<Gitignore>,
+    ignore_git_cache: HashMap<PathBuf, Option<Arc<Gitignore>>>,
+    glob: Option<GlobFilter>,
 }
 
 impl Default for PipelineFinder {
@@ -54,23 +158,37 @@ impl PipelineFinder {
             skip_ambignore: true,
             print_skipped: false,
             find_parent_ignore: true,
+            git_scope: None,
+            from_list: false,
+            dispatch_window: 16,
+            matcher_num: 1,
+            sort_path: false,
+            sort_reverse: false,
+            print_progress: false,
+            gate: None,
             infos: Vec::new(),
             errors: Vec::new(),
+            statistics: Statistics::default(),
             time_beg: Instant::now(),
             time_bsy: Duration::new(0, 0),
+            id: 0,
             seq_no: 0,
-            current_tx: 0,
+            progress: Progress::default(),
+            pending: Vec::new(),
             ignore_vcs: IgnoreVcs::new(),
             ignore_git: Vec::new(),
+            ignore_git_cache: HashMap::new(),
+            glob: None,
         }
     }
 
-    fn find_path(&mut self, base: PathBuf, tx: &Vec<Sender<PipelineInfo<PathInfo>>>, is_symlink: bool) {
+    fn find_path(&mut self, base: PathBuf, tx: &Sender<PipelineInfo<PathInfo>>, is_symlink: bool) {
         let attr = match fs::metadata(&base) {
             Ok(x) => x,
             Err(e) => {
                 if !is_symlink {
-                    self.errors.push(format!("Error: {} @ {}", e, base.to_str().unwrap()));
+                    self.errors
+                        .push(PipelineError::new(PipelineStage::Finder, Some(&base), &e));
                 }
                 return;
             }
@@ -78,56 +196,205 @@ impl PipelineFinder {
 
         if attr.is_file() {
             if attr.len() != 0 {
-                self.send_path(base, tx);
+                self.send_path(base, attr.len(), tx);
             }
         } else {
             let reader = match fs::read_dir(&base) {
                 Ok(x) => x,
                 Err(e) => {
-                    self.errors.push(format!("Error: {} @ {}", e, base.to_str().unwrap()));
+                    self.errors
+                        .push(PipelineError::new(PipelineStage::Finder, Some(&base), &e));
                     return;
                 }
             };
 
             let gitignore_exist = self.push_gitignore(&base);
 
+            let mut entries = Vec::new();
             for i in reader {
                 match i {
-                    Ok(entry) => {
-                        let file_type = match entry.file_type() {
-                            Ok(x) => x,
-                            Err(e) => {
-                                self.errors.push(format!("Error: {}", e));
-                                continue;
-                            }
-                        };
-                        if file_type.is_file() {
-                            self.send_path(entry.path(), tx);
-                        } else {
-                            let find_dir = file_type.is_dir() & self.is_recursive;
-                            let find_symlink = file_type.is_symlink() & self.is_recursive & self.follow_symlink;
-                            if (find_dir | find_symlink) & self.check_path(&entry.path(), true) {
-                                self.find_path(entry.path(), tx, find_symlink);
-                            }
-                        }
+                    Ok(x) => entries.push(x),
+                    Err(e) => self
+                        .errors
+                        .push(PipelineError::new(PipelineStage::Finder, Some(&base), &e)),
+                }
+            }
+            // Entries are walked in path order, so the found files are also in path order
+            if self.sort_path {
+                entries.sort_by_key(|x| x.file_name());
+                if self.sort_reverse {
+                    entries.reverse();
+                }
+            }
+
+            for entry in entries {
+                let file_type = match entry.file_type() {
+                    Ok(x) => x,
+                    Err(e) => {
+                        self.errors
+                            .push(PipelineError::new(PipelineStage::Finder, Some(&entry.path()), &e));
+                        continue;
                     }
-                    Err(e) => self.errors.push(format!("Error: {}", e)),
                 };
+                if file_type.is_file() {
+                    let path = entry.path();
+                    if self.check_glob(&path, false) && self.check_path(&path, false) {
+                        // The size is read through the entry only if the file is searched
+                        let size = entry.metadata().map(|x| x.len()).unwrap_or(0);
+                        self.dispatch_path(path, size, tx);
+                    }
+                } else {
+                    let find_dir = file_type.is_dir() & self.is_recursive;
+                    let find_symlink = file_type.is_symlink() & self.is_recursive & self.follow_symlink;
+                    if (find_dir | find_symlink)
+                        && self.check_glob(&entry.path(), true)
+                        && self.check_path(&entry.path(), true)
+                    {
+                        self.find_path(entry.path(), tx, find_symlink);
+                    }
+                }
             }
 
             self.pop_gitignore(gitignore_exist)
         }
     }
 
-    fn send_path(&mut self, path: PathBuf, tx: &[Sender<PipelineInfo<PathInfo>>]) {
-        if self.check_path(&path, false) {
-            let _ = tx[self.current_tx].send(PipelineInfo::SeqDat(self.seq_no, PathInfo { path }));
-            self.seq_no += 1;
-            self.current_tx = if self.current_tx == tx.len() - 1 {
-                0
-            } else {
-                self.current_tx + 1
+    fn find_git_path(&mut self, base: PathBuf, tx: &Sender<PipelineInfo<PathInfo>>, scope: &GitScope) {
+        let paths = match scope.list_files(&base) {
+            Ok(x) => x,
+            Err(e) => {
+                self.errors
+                    .push(PipelineError::new(PipelineStage::Finder, Some(&base), &e));
+                return;
+            }
+        };
+
+        for path in paths {
+            if !self.is_recursive && base.is_dir() && path.parent() != Some(base.as_path()) {
+                continue;
+            }
+            // Deleted files may be listed by git, and empty files are skipped as the walk does
+            let size = match fs::metadata(&path) {
+                Ok(ref x) if x.is_file() && x.len() != 0 => x.len(),
+                _ => continue,
             };
+            if self.check_path(&path, false) {
+                self.dispatch_path(path, size, tx);
+            }
+        }
+    }
+
+    fn find_listed_path(&mut self, path: PathBuf, tx: &Sender<PipelineInfo<PathInfo>>) {
+        let attr = match fs::metadata(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                self.errors
+                    .push(PipelineError::new(PipelineStage::Finder, Some(&path), &e));
+                return;
+            }
+        };
+
+        if !attr.is_file() {
+            self.skip(&path, SkipReason::NotFile);
+            return;
+        }
+
+        if attr.len() != 0 && self.check_listed_path(&path, false) {
+            self.dispatch_path(path, attr.len(), tx);
+        }
+    }
+
+    fn send_path(&mut self, path: PathBuf, size: u64, tx: &Sender<PipelineInfo<PathInfo>>) {
+        if self.check_glob(&path, false) && self.check_path(&path, false) {
+            self.dispatch_path(path, size, tx);
+        }
+    }
+
+    /// Numbers the found file, and holds it until `dispatch_window` files are pending.
+    /// The largest pending file is sent first so that it does not start at the tail of the search.
+    fn dispatch_path(&mut self, path: PathBuf, size: u64, tx: &Sender<PipelineInfo<PathInfo>>) {
+        self.pending.push(PendingPath {
+            seq: self.seq_no,
+            info: PathInfo { path, size },
+        });
+        self.seq_no += 1;
+        if self.pending.len() > self.dispatch_window {
+            self.send_pending(tx);
+        }
+
+        self.progress.files_found += 1;
+        self.statistics.files_walked += 1;
+        self.send_progress(tx, false);
+    }
+
+    fn skip(&mut self, path: &Path, reason: SkipReason) {
+        self.statistics.skip(reason);
+        if self.print_skipped {
+            self.infos.push(PipelineNotice::Skipped {
+                path: path.to_path_buf(),
+                reason,
+            });
+        }
+    }
+
+    fn send_progress(&mut self, tx: &Sender<PipelineInfo<PathInfo>>, force: bool) {
+        if self.print_progress {
+            if let Some(x) = self.progress.throttle(force) {
+                let _ = tx.send(PipelineInfo::MsgProgress(self.id, x));
+            }
+        }
+    }
+
+    fn send_pending(&mut self, tx: &Sender<PipelineInfo<PathInfo>>) {
+        // The oldest file is sent if it has been held for `dispatch_window` files, so that the numbers in flight stay
+        // within `2 * dispatch_window + 1` of it
+        let oldest_expired = self
+            .pending
+            .first()
+            .is_some_and(|x| x.seq + 2 * self.dispatch_window < self.seq_no);
+        let index = if oldest_expired {
+            0
+        } else {
+            match self
+                .pending
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, x)| Reverse(x.info.size))
+            {
+                Some((i, _)) => i,
+                None => return,
+            }
+        };
+
+        let x = self.pending.remove(index);
+        if let Some(ref gate) = self.gate {
+            gate.wait(x.seq);
+        }
+        let _ = tx.send(PipelineInfo::SeqDat(x.seq, x.info));
+    }
+
+    fn find_walk_path(&mut self, base: PathBuf, tx: &Sender<PipelineInfo<PathInfo>>) {
+        let base = match GlobFilter::new(&base) {
+            Some(Ok(glob)) => {
+                let base = glob.base.clone();
+                self.glob = Some(glob);
+                base
+            }
+            Some(Err(e)) => {
+                self.errors.push(e);
+                return;
+            }
+            None => base,
+        };
+        let base = self.set_default_gitignore(&base);
+        self.find_path(base, tx, false);
+        self.glob = None;
+    }
+
+    fn check_glob(&self, path: &Path, is_dir: bool) -> bool {
+        match self.glob {
+            Some(ref glob) => glob.is_match(path, is_dir),
+            None => true,
         }
     }
 
@@ -145,7 +412,9 @@ impl PipelineFinder {
                             return true;
                         }
                     }
-                    Err(e) => self.errors.push(format!("Error: {}", e)),
+                    Err(e) => self
+                        .errors
+                        .push(PipelineError::new(PipelineStage::Finder, Some(path), &e)),
                 }
             }
         }
@@ -158,7 +427,7 @@ impl PipelineFinder {
         }
     }
 
-    fn check_path(&mut self, path: &PathBuf, is_dir: bool) -> bool {
+    fn check_path(&mut self, path: &Path, is_dir: bool) -> bool {
         let ok_vcs = if self.skip_vcs {
             !self.ignore_vcs.is_ignore(path, is_dir)
         } else {
@@ -171,17 +440,83 @@ impl PipelineFinder {
             true
         };
 
-        if !ok_vcs & self.print_skipped {
-            self.infos.push(format!("Skip (vcs file)  : {:?}", path));
+        if !ok_vcs {
+            self.skip(path, SkipReason::Vcs);
+        }
+
+        if !ok_git {
+            self.skip(path, SkipReason::Gitignore);
+        }
+
+        ok_vcs && ok_git
+    }
+
+    // Paths which are not reached by the directory walk are checked against all of their ancestors
+    fn check_listed_path(&mut self, path: &Path, is_dir: bool) -> bool {
+        let ok_vcs = if self.skip_vcs {
+            let mut ancestors = path.ancestors();
+            if !is_dir {
+                let _ = ancestors.next();
+            }
+            !ancestors.any(|x| self.ignore_vcs.is_ignore(x, true))
+        } else {
+            true
+        };
+
+        let ok_git = if self.skip_gitignore {
+            // The nearest .gitignore which has a matched rule takes precedence over the parents
+            let ignores = self.find_listed_gitignore(path);
+            let matched = ignores
+                .iter()
+                .map(|x| x.matched_path_or_any_parents(path, is_dir))
+                .find(|x| !x.is_none());
+            !matches!(matched, Some(::ignore::Match::Ignore(_)))
+        } else {
+            true
+        };
+
+        if !ok_vcs {
+            self.skip(path, SkipReason::Vcs);
         }
 
-        if !ok_git & self.print_skipped {
-            self.infos.push(format!("Skip (.gitignore): {:?}", path));
+        if !ok_git {
+            self.skip(path, SkipReason::Gitignore);
         }
 
         ok_vcs && ok_git
     }
 
+    /// Find .gitignore files of the ancestors of the path, where the nearest one is the first.
+    fn find_listed_gitignore(&mut self, path: &Path) -> Vec<Arc<Gitignore>> {
+        let mut ret = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            let ignore = self
+                .ignore_git_cache
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| {
+                    let file = if dir.as_os_str().is_empty() {
+                        PathBuf::from(".gitignore")
+                    } else {
+                        dir.join(".gitignore")
+                    };
+                    if file.is_file() {
+                        Some(Arc::new(Gitignore::new(file).0))
+                    } else {
+                        None
+                    }
+                })
+                .clone();
+
+            if let Some(ignore) = ignore {
+                ret.push(ignore);
+            }
+            if !self.find_parent_ignore {
+                break;
+            }
+        }
+        ret
+    }
+
     fn set_default_gitignore(&mut self, base: &Path) -> PathBuf {
         if !self.skip_gitignore {
             return base.to_path_buf();
@@ -192,10 +527,8 @@ impl PipelineFinder {
 
         let base_abs = match base.canonicalize() {
             Ok(x) => x,
-            Err(e) => {
-                self.errors.push(format!("Error: {} @ {}", e, base.to_str().unwrap()));
-                return base.to_path_buf();
-            }
+            // The error is reported by the walk of `base`
+            Err(_) => return base.to_path_buf(),
         };
 
         let mut parent_abs = base_abs.parent();
@@ -207,8 +540,7 @@ impl PipelineFinder {
         }
         while parent_abs.is_some() {
             if self.push_gitignore(&PathBuf::from(&parent)) {
-                self.infos
-                    .push(format!("Found .gitignore at the parent directory: {:?}\n", parent));
+                self.infos.push(PipelineNotice::ParentGitignore(parent));
                 return base.to_path_buf();
             }
             parent_abs = parent_abs.unwrap().parent();
@@ -219,18 +551,25 @@ impl PipelineFinder {
     }
 }
 
-impl PipelineFork<PathBuf, PathInfo> for PipelineFinder {
-    fn setup(&mut self, id: usize, rx: Receiver<PipelineInfo<PathBuf>>, tx: Vec<Sender<PipelineInfo<PathInfo>>>) {
+impl Pipeline<PathBuf, PathInfo> for PipelineFinder {
+    fn setup(&mut self, id: usize, rx: Receiver<PipelineInfo<PathBuf>>, tx: Sender<PipelineInfo<PathInfo>>) {
         self.infos = Vec::new();
         self.errors = Vec::new();
+        self.statistics = Statistics::default();
+        self.id = id;
         let mut seq_beg_arrived = false;
 
         loop {
             match rx.recv() {
                 Ok(PipelineInfo::SeqDat(_, p)) => {
                     watch_time!(self.time_bsy, {
-                        let p = self.set_default_gitignore(&p);
-                        self.find_path(p, &tx, false);
+                        if let Some(scope) = self.git_scope.clone() {
+                            self.find_git_path(p, &tx, &scope);
+                        } else if self.from_list {
+                            self.find_listed_path(p, &tx);
+                        } else {
+                            self.find_walk_path(p, &tx);
+                        }
                     });
                 }
 
@@ -239,7 +578,8 @@ impl PipelineFork<PathBuf, PathInfo> for PipelineFinder {
                         self.seq_no = x;
                         self.time_beg = Instant::now();
 
-                        for tx in &tx {
+                        // The matchers share the output, so each of them receives its own SeqBeg
+                        for _ in 0..self.matcher_num {
                             let _ = tx.send(PipelineInfo::SeqBeg(x));
                         }
 
@@ -248,16 +588,22 @@ impl PipelineFork<PathBuf, PathInfo> for PipelineFinder {
                 }
 
                 Ok(PipelineInfo::SeqEnd(_)) => {
+                    while !self.pending.is_empty() {
+                        self.send_pending(&tx);
+                    }
+                    self.send_progress(&tx, true);
+
                     for i in &self.infos {
-                        let _ = tx[0].send(PipelineInfo::MsgInfo(id, i.clone()));
+                        let _ = tx.send(PipelineInfo::MsgInfo(id, i.clone()));
                     }
                     for e in &self.errors {
-                        let _ = tx[0].send(PipelineInfo::MsgErr(id, e.clone()));
+                        let _ = tx.send(PipelineInfo::MsgErr(id, e.clone()));
                     }
 
-                    let _ = tx[0].send(PipelineInfo::MsgTime(id, self.time_bsy, self.time_beg.elapsed()));
+                    let _ = tx.send(PipelineInfo::MsgStat(id, self.statistics.clone()));
+                    let _ = tx.send(PipelineInfo::MsgTime(id, self.time_bsy, self.time_beg.elapsed()));
 
-                    for tx in &tx {
+                    for _ in 0..self.matcher_num {
                         let _ = tx.send(PipelineInfo::SeqEnd(self.seq_no));
                     }
 
@@ -265,16 +611,22 @@ impl PipelineFork<PathBuf, PathInfo> for PipelineFinder {
                 }
 
                 Ok(PipelineInfo::MsgDebug(i, e)) => {
-                    let _ = tx[0].send(PipelineInfo::MsgDebug(i, e));
+                    let _ = tx.send(PipelineInfo::MsgDebug(i, e));
                 }
                 Ok(PipelineInfo::MsgInfo(i, e)) => {
-                    let _ = tx[0].send(PipelineInfo::MsgInfo(i, e));
+                    let _ = tx.send(PipelineInfo::MsgInfo(i, e));
                 }
                 Ok(PipelineInfo::MsgErr(i, e)) => {
-                    let _ = tx[0].send(PipelineInfo::MsgErr(i, e));
+                    let _ = tx.send(PipelineInfo::MsgErr(i, e));
                 }
                 Ok(PipelineInfo::MsgTime(i, t0, t1)) => {
-                    let _ = tx[0].send(PipelineInfo::MsgTime(i, t0, t1));
+                    let _ = tx.send(PipelineInfo::MsgTime(i, t0, t1));
+                }
+                Ok(PipelineInfo::MsgProgress(i, x)) => {
+                    let _ = tx.send(PipelineInfo::MsgProgress(i, x));
+                }
+                Ok(PipelineInfo::MsgStat(i, x)) => {
+                    let _ = tx.send(PipelineInfo::MsgStat(i, x));
                 }
                 Err(_) => break,
             }
@@ -289,31 +641,34 @@ impl PipelineFork<PathBuf, PathInfo> for PipelineFinder {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::pipeline::{PipelineFork, PipelineInfo};
+    use crate::pipeline::{Pipeline, PipelineInfo};
     use crossbeam::channel::unbounded;
     use std::path::{Path, PathBuf};
     use std::thread;
 
-    fn test<T: 'static + PipelineFork<PathBuf, PathInfo> + Send>(mut finder: T, path: String) -> Vec<PathInfo> {
+    fn test<T: 'static + Pipeline<PathBuf, PathInfo> + Send>(mut finder: T, paths: Vec<&str>) -> Vec<PathInfo> {
         let (in_tx, in_rx) = unbounded();
         let (out_tx, out_rx) = unbounded();
         thread::spawn(move || {
-            finder.setup(0, in_rx, vec![out_tx]);
+            finder.setup(0, in_rx, out_tx);
         });
         let _ = in_tx.send(PipelineInfo::SeqBeg(0));
-        let _ = in_tx.send(PipelineInfo::SeqDat(0, PathBuf::from(path)));
-        let _ = in_tx.send(PipelineInfo::SeqEnd(1));
+        for (i, path) in paths.iter().enumerate() {
+            let _ = in_tx.send(PipelineInfo::SeqDat(i, PathBuf::from(path)));
+        }
+        let _ = in_tx.send(PipelineInfo::SeqEnd(paths.len()));
 
         let mut ret = Vec::new();
         loop {
             match out_rx.recv().unwrap() {
-                PipelineInfo::SeqDat(_, x) => ret.push(x),
+                PipelineInfo::SeqDat(i, x) => ret.push((i, x)),
                 PipelineInfo::SeqEnd(_) => break,
                 _ => (),
             }
         }
 
-        ret
+        ret.sort_by_key(|x| x.0);
+        ret.into_iter().map(|x| x.1).collect()
     }
 
     #[test]
@@ -324,7 +679,7 @@ mod tests {
         }
 
         let finder = PipelineFinder::new();
-        let ret = test(finder, "./".to_string());
+        let ret = test(finder, vec!["./"]);
 
         assert!(ret.iter().any(|x| x.path == PathBuf::from("./Cargo.toml")));
         assert!(ret.iter().any(|x| x.path == PathBuf::from("./src/ambr.rs")));
@@ -345,7 +700,7 @@ mod tests {
 
         let mut finder = PipelineFinder::new();
         finder.skip_vcs = false;
-        let ret = test(finder, "./".to_string());
+        let ret = test(finder, vec!["./"]);
 
         assert!(ret.iter().any(|x| x.path == PathBuf::from("./Cargo.toml")));
         assert!(ret.iter().any(|x| x.path == PathBuf::from("./src/ambr.rs")));
@@ -356,4 +711,122 @@ mod tests {
         assert!(ret.iter().any(|x| x.path == PathBuf::from("./src/util.rs")));
         assert!(ret.iter().any(|x| x.path == PathBuf::from("./.git/config")));
     }
+
+    #[test]
+    fn pipeline_finder_git_tracked() {
+        if GitScope::Tracked.list_files(Path::new("./")).is_err() {
+            return;
+        }
+
+        let mut finder = PipelineFinder::new();
+        finder.git_scope = Some(GitScope::Tracked);
+        let ret = test(finder, vec!["./"]);
+
+        assert!(ret.iter().any(|x| x.path == Path::new("./Cargo.toml")));
+        assert!(ret.iter().any(|x| x.path == Path::new("./src/lib.rs")));
+        assert!(!ret.iter().any(|x| x.path == Path::new("./test/ao")));
+        assert!(!ret.iter().any(|x| x.path.starts_with("./target")));
+        assert!(!ret.iter().any(|x| x.path == Path::new("./.git/config")));
+    }
+
+    #[test]
+    fn pipeline_finder_from_list() {
+        if !Path::new("./.git/config").exists() {
+            fs::create_dir_all("./.git").unwrap();
+            fs::File::create("./.git/config").unwrap();
+        }
+
+        // Ignored by *.so of ./.gitignore, though ./test/.gitignore is nearer
+        fs::write("./test/x/a.so", "a").unwrap();
+
+        let mut finder = PipelineFinder::new();
+        finder.from_list = true;
+        let ret = test(
+            finder,
+            vec![
+                "./src/lib.rs",
+                "./src",
+                "./.git/config",
+                "./test/.hg/file",
+                "./test/a.o",
+                "./test/abc.s",
+                "./test/dir7/file",
+                "./test/x/a.so",
+                "Cargo.toml",
+            ],
+        );
+
+        assert_eq!(ret.len(), 3);
+        assert_eq!(ret[0].path, Path::new("./src/lib.rs"));
+        assert_eq!(ret[1].path, Path::new("./test/abc.s"));
+        assert_eq!(ret[2].path, Path::new("Cargo.toml"));
+    }
+
+    #[test]
+    fn pipeline_finder_glob() {
+        let finder = PipelineFinder::new();
+        let ret = test(finder, vec!["./src/*.rs", "test/**/file", "test/x/dir*"]);
+
+        assert!(ret.iter().any(|x| x.path == Path::new("./src/lib.rs")));
+        assert!(ret.iter().any(|x| x.path == Path::new("test/x/file")));
+        assert!(ret.iter().any(|x| x.path == Path::new("test/x/dir0/file")));
+        assert!(ret.iter().any(|x| x.path == Path::new("test/x/dir1/file")));
+        assert!(!ret.iter().any(|x| x.path == Path::new("./Cargo.toml")));
+        assert!(!ret.iter().any(|x| x.path == Path::new("test/dir0/file")));
+        assert!(!ret.iter().any(|x| x.path == Path::new("test/.hg/file")));
+        assert_eq!(ret.iter().filter(|x| x.path.starts_with("test/x/dir0")).count(), 2);
+    }
+
+    #[test]
+    fn pipeline_finder_dispatch_window() {
+        let mut finder = PipelineFinder::new();
+        finder.from_list = true;
+        finder.dispatch_window = 8;
+
+        let (in_tx, in_rx) = unbounded();
+        let (out_tx, out_rx) = unbounded();
+        thread::spawn(move || {
+            finder.setup(0, in_rx, out_tx);
+        });
+        let paths = ["./src/lib.rs", "./src/pipeline_finder.rs", "./Cargo.toml"];
+        let _ = in_tx.send(PipelineInfo::SeqBeg(0));
+        for (i, path) in paths.iter().enumerate() {
+            let _ = in_tx.send(PipelineInfo::SeqDat(i, PathBuf::from(path)));
+        }
+        let _ = in_tx.send(PipelineInfo::SeqEnd(paths.len()));
+
+        let mut ret = Vec::new();
+        loop {
+            match out_rx.recv().unwrap() {
+                PipelineInfo::SeqDat(i, x) => ret.push((i, x)),
+                PipelineInfo::SeqEnd(_) => break,
+                _ => (),
+            }
+        }
+
+        assert_eq!(ret.len(), 3);
+        assert!(ret.windows(2).all(|x| x[0].1.size >= x[1].1.size));
+        for (i, x) in &ret {
+            assert_eq!(x.path, Path::new(paths[*i]));
+        }
+    }
+
+    #[test]
+    fn pipeline_finder_sort_path() {
+        let mut finder = PipelineFinder::new();
+        finder.sort_path = true;
+        let ret = test(finder, vec!["./src"]);
+        let paths: Vec<_> = ret.iter().map(|x| x.path.clone()).collect();
+        let mut sorted = paths.clone();
+        sorted.sort();
+        assert_eq!(paths, sorted);
+
+        let mut finder = PipelineFinder::new();
+        finder.sort_path = true;
+        finder.sort_reverse = true;
+        let ret = test(finder, vec!["./src"]);
+        let paths: Vec<_> = ret.iter().map(|x| x.path.clone()).collect();
+        sorted.reverse();
+        assert_eq!(paths, sorted);
+    }
 }
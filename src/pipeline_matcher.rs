@@ -1,13 +1,20 @@
-use crate::matcher::{Match, Matcher};
-use crate::pipeline::{Pipeline, PipelineInfo};
+use crate::console::Console;
+use crate::matcher::{ChunkPool, Match, Matcher, SharedSrc};
+use crate::pipeline::{
+    Pipeline, PipelineError, PipelineInfo, PipelineNotice, PipelineStage, Progress, SkipReason, Statistics,
+};
 use crate::pipeline_finder::PathInfo;
-use crate::util::{catch, decode_error};
+use crate::util::catch;
 use crossbeam::channel::{Receiver, Sender};
+use crossbeam::select;
 use memmap::Mmap;
+use std::fmt;
 use std::fs::{self, File};
 use std::io::{Error, Read};
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant};
 
 // ---------------------------------------------------------------------------------------------------------------------
@@ -18,6 +25,141 @@ use std::time::{Duration, Instant};
 pub struct PathMatch {
     pub path: PathBuf,
     pub matches: Vec<Match>,
+    pub skipped: bool,
+    /// Contents which the matches are found in, which are kept only if `keep_contents` is set and any match is found
+    pub contents: Option<Arc<FileContents>>,
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// FileContents
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// Contents of a file loaded by the matcher.
+///
+/// The later stages render the exact bytes which were matched through it, instead of reading the file again, which may
+/// be changed by a concurrent writer.
+/// A file over `mmap_bytes` is still mapped as shared, so truncating it before the later stages read it may cause SIGBUS
+/// as the search of the mapped file does.
+pub struct FileContents {
+    data: FileData,
+    /// Start positions of lines, which are built at the first use because some outputs don't need them
+    line_starts: OnceLock<Vec<usize>>,
+    /// The budget which the size of the contents is reserved from, until they are dropped
+    reserved: Option<Arc<ContentsBudget>>,
+}
+
+enum FileData {
+    Mmap(Mmap),
+    Buffer(Vec<u8>),
+}
+
+impl FileData {
+    fn src(&self) -> &[u8] {
+        match self {
+            FileData::Mmap(x) => x.deref(),
+            FileData::Buffer(x) => &x[..],
+        }
+    }
+}
+
+impl FileContents {
+    fn new(data: FileData) -> Self {
+        FileContents {
+            data,
+            line_starts: OnceLock::new(),
+            reserved: None,
+        }
+    }
+
+    /// Reserve the size of the contents from `budget`, and return false if it is over the budget.
+    fn reserve(&mut self, budget: &Arc<ContentsBudget>) -> bool {
+        let ret = budget.reserve(self.src().len());
+        if ret {
+            self.reserved = Some(budget.clone());
+        }
+        ret
+    }
+
+    /// Load a file whose contents were not kept by the matcher.
+    pub fn open(path: &Path) -> Result<FileContents, Error> {
+        let mut file = File::open(path)?;
+        // mmap of an empty file fails
+        let data = if file.metadata()?.len() > 0 {
+            FileData::Mmap(unsafe { Mmap::map(&file) }?)
+        } else {
+            let mut buf = Vec::new();
+            file.read_to_end(&mut buf)?;
+            FileData::Buffer(buf)
+        };
+        Ok(FileContents::new(data))
+    }
+
+    pub fn src(&self) -> &[u8] {
+        self.data.src()
+    }
+
+    /// Get the start positions of lines given by `Console::get_line_starts`.
+    pub fn line_starts(&self) -> &[usize] {
+        self.line_starts
+            .get_or_init(|| Console::get_line_starts(self.data.src()))
+    }
+}
+
+impl Drop for FileContents {
+    fn drop(&mut self) {
+        if let Some(ref budget) = self.reserved {
+            budget.release(self.src().len());
+        }
+    }
+}
+
+impl AsRef<[u8]> for FileContents {
+    fn as_ref(&self) -> &[u8] {
+        self.src()
+    }
+}
+
+impl fmt::Debug for FileContents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.data {
+            FileData::Mmap(_) => "mmap",
+            FileData::Buffer(_) => "buffer",
+        };
+        write!(f, "FileContents({}, {} bytes)", kind, self.src().len())
+    }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// ContentsBudget
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// A limit of bytes of the contents kept for the later stages, which are held while the results wait to be output.
+///
+/// The contents over the limit are not kept, and the later stages read the file again.
+pub struct ContentsBudget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl ContentsBudget {
+    pub fn new(limit: usize) -> Self {
+        ContentsBudget {
+            limit,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    fn reserve(&self, bytes: usize) -> bool {
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes).filter(|x| *x <= self.limit)
+            })
+            .is_ok()
+    }
+
+    fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::SeqCst);
+    }
 }
 
 // ---------------------------------------------------------------------------------------------------------------------
@@ -28,12 +170,22 @@ pub struct PipelineMatcher<T: Matcher> {
     pub skip_binary: bool,
     pub print_skipped: bool,
     pub print_search: bool,
+    pub skip_search: bool,
+    pub print_progress: bool,
     pub binary_check_bytes: usize,
     pub mmap_bytes: u64,
-    pub infos: Vec<String>,
-    pub errors: Vec<String>,
+    /// Pass the contents of files with matches to the later stages
+    pub keep_contents: bool,
+    /// The limit of the contents passed to the later stages, which is shared by all matchers
+    pub contents_budget: Option<Arc<ContentsBudget>>,
+    /// Chunks of large files which are searched before the next file
+    pub pool: Option<Arc<ChunkPool>>,
+    pub infos: Vec<PipelineNotice>,
+    pub errors: Vec<PipelineError>,
+    pub statistics: Statistics,
     time_beg: Instant,
     time_bsy: Duration,
+    progress: Progress,
     matcher: T,
     keyword: Vec<u8>,
 }
@@ -44,12 +196,19 @@ impl<T: Matcher> PipelineMatcher<T> {
             skip_binary: true,
             print_skipped: false,
             print_search: false,
+            skip_search: false,
+            print_progress: false,
             binary_check_bytes: 128,
             mmap_bytes: 1024 * 1024,
+            keep_contents: true,
+            contents_budget: None,
+            pool: None,
             infos: Vec::new(),
             errors: Vec::new(),
+            statistics: Statistics::default(),
             time_beg: Instant::now(),
             time_bsy: Duration::new(0, 0),
+            progress: Progress::default(),
             matcher,
             keyword: Vec::from(keyword),
         }
@@ -66,17 +225,32 @@ impl<T: Matcher> PipelineMatcher<T> {
                 }
             };
 
-            let mmap;
             let mut buf = Vec::new();
-            let src = if attr.len() > self.mmap_bytes {
+            let data = if self.skip_search {
+                // Only the binary check is required
+                let f = File::open(&info.path)?;
+                f.take(self.binary_check_bytes as u64).read_to_end(&mut buf)?;
+                self.statistics.read_files += 1;
+                FileData::Buffer(buf)
+            } else if attr.len() > self.mmap_bytes {
                 let file = File::open(&info.path)?;
-                mmap = unsafe { Mmap::map(&file) }?;
-                mmap.deref()
+                self.statistics.mmap_files += 1;
+                FileData::Mmap(unsafe { Mmap::map(&file) }?)
             } else {
                 let mut f = File::open(&info.path)?;
                 f.read_to_end(&mut buf)?;
-                &buf[..]
+                self.statistics.read_files += 1;
+                FileData::Buffer(buf)
             };
+            let mut contents = FileContents::new(data);
+            let keep_contents = self.keep_contents
+                && match self.contents_budget {
+                    Some(ref x) => contents.reserve(x),
+                    None => true,
+                };
+            let contents = Arc::new(contents);
+            let src = contents.src();
+            self.statistics.bytes_read += src.len() as u64;
 
             if self.skip_binary {
                 let mut is_binary = false;
@@ -92,21 +266,40 @@ impl<T: Matcher> PipelineMatcher<T> {
                     }
                 }
                 if is_binary {
+                    self.statistics.skip(SkipReason::Binary);
                     if self.print_skipped {
-                        self.infos.push(format!("Skip (binary)    : {:?}", info.path));
+                        self.infos.push(PipelineNotice::Skipped {
+                            path: info.path.clone(),
+                            reason: SkipReason::Binary,
+                        });
                     }
                     return Ok(PathMatch {
                         path: info.path.clone(),
                         matches: Vec::new(),
+                        skipped: true,
+                        contents: None,
                     });
                 }
             }
 
-            let ret = self.matcher.search(src, &self.keyword);
+            let ret = if self.skip_search {
+                Vec::new()
+            } else {
+                let shared: SharedSrc = contents.clone();
+                self.matcher.search_shared(&shared, &self.keyword)
+            };
+
+            let contents = if keep_contents && !ret.is_empty() {
+                Some(contents)
+            } else {
+                None
+            };
 
             Ok(PathMatch {
                 path: info.path.clone(),
                 matches: ret,
+                skipped: false,
+                contents,
             })
         });
 
@@ -114,10 +307,12 @@ impl<T: Matcher> PipelineMatcher<T> {
             Ok(x) => x,
             Err(e) => {
                 self.errors
-                    .push(format!("Error: {} @ {:?}\n", decode_error(e.kind()), path_org));
+                    .push(PipelineError::new(PipelineStage::Matcher, Some(&path_org), &e));
                 PathMatch {
                     path: info.path.clone(),
                     matches: Vec::new(),
+                    skipped: true,
+                    contents: None,
                 }
             }
         }
@@ -128,10 +323,34 @@ impl<T: Matcher> Pipeline<PathInfo, PathMatch> for PipelineMatcher<T> {
     fn setup(&mut self, id: usize, rx: Receiver<PipelineInfo<PathInfo>>, tx: Sender<PipelineInfo<PathMatch>>) {
         self.infos = Vec::new();
         self.errors = Vec::new();
+        self.statistics = Statistics::default();
         let mut seq_beg_arrived = false;
 
         loop {
-            match rx.recv() {
+            let msg = match self.pool.clone() {
+                Some(pool) => {
+                    if let Ok(job) = pool.jobs().try_recv() {
+                        watch_time!(self.time_bsy, {
+                            job.run();
+                        });
+                        continue;
+                    }
+                    select! {
+                        recv(pool.jobs()) -> job => {
+                            if let Ok(job) = job {
+                                watch_time!(self.time_bsy, {
+                                    job.run();
+                                });
+                            }
+                            continue;
+                        }
+                        recv(rx) -> x => x,
+                    }
+                }
+                None => rx.recv(),
+            };
+
+            match msg {
                 Ok(PipelineInfo::SeqDat(x, p)) => {
                     let mut path = None;
                     if self.print_search {
@@ -139,7 +358,23 @@ impl<T: Matcher> Pipeline<PathInfo, PathMatch> for PipelineMatcher<T> {
                         let _ = tx.send(PipelineInfo::MsgDebug(id, format!("Search Start     : {:?}", p.path)));
                     }
                     watch_time!(self.time_bsy, {
+                        if self.print_progress {
+                            self.progress.current = Some(p.path.clone());
+                            if let Some(x) = self.progress.throttle(false) {
+                                let _ = tx.send(PipelineInfo::MsgProgress(id, x));
+                            }
+                        }
+                        let size = p.size;
                         let ret = self.search_path(p);
+                        self.progress.files_searched += 1;
+                        if !ret.skipped {
+                            self.progress.bytes_scanned += size;
+                        }
+                        self.progress.matches += ret.matches.len();
+                        if !ret.skipped {
+                            self.statistics.files_searched += 1;
+                        }
+                        self.statistics.matches += ret.matches.len();
                         let _ = tx.send(PipelineInfo::SeqDat(x, ret));
                     });
                     if self.print_search {
@@ -159,6 +394,13 @@ impl<T: Matcher> Pipeline<PathInfo, PathMatch> for PipelineMatcher<T> {
                 }
 
                 Ok(PipelineInfo::SeqEnd(x)) => {
+                    if self.print_progress {
+                        self.progress.current = None;
+                        if let Some(p) = self.progress.throttle(true) {
+                            let _ = tx.send(PipelineInfo::MsgProgress(id, p));
+                        }
+                    }
+
                     for i in &self.infos {
                         let _ = tx.send(PipelineInfo::MsgInfo(id, i.clone()));
                     }
@@ -166,6 +408,7 @@ impl<T: Matcher> Pipeline<PathInfo, PathMatch> for PipelineMatcher<T> {
                         let _ = tx.send(PipelineInfo::MsgErr(id, e.clone()));
                     }
 
+                    let _ = tx.send(PipelineInfo::MsgStat(id, self.statistics.clone()));
                     let _ = tx.send(PipelineInfo::MsgTime(id, self.time_bsy, self.time_beg.elapsed()));
                     let _ = tx.send(PipelineInfo::SeqEnd(x));
                     break;
@@ -183,6 +426,12 @@ impl<T: Matcher> Pipeline<PathInfo, PathMatch> for PipelineMatcher<T> {
                 Ok(PipelineInfo::MsgTime(i, t0, t1)) => {
                     let _ = tx.send(PipelineInfo::MsgTime(i, t0, t1));
                 }
+                Ok(PipelineInfo::MsgProgress(i, x)) => {
+                    let _ = tx.send(PipelineInfo::MsgProgress(i, x));
+                }
+                Ok(PipelineInfo::MsgStat(i, x)) => {
+                    let _ = tx.send(PipelineInfo::MsgStat(i, x));
+                }
                 Err(_) => break,
             }
         }
@@ -219,18 +468,21 @@ mod tests {
             0,
             PathInfo {
                 path: PathBuf::from("./src/ambs.rs"),
+                size: 0,
             },
         ));
         let _ = in_tx.send(PipelineInfo::SeqDat(
             1,
             PathInfo {
                 path: PathBuf::from("./src/ambr.rs"),
+                size: 0,
             },
         ));
         let _ = in_tx.send(PipelineInfo::SeqDat(
             2,
             PathInfo {
                 path: PathBuf::from("./src/console.rs"),
+                size: 0,
             },
         ));
         let _ = in_tx.send(PipelineInfo::SeqEnd(3));
@@ -256,4 +508,216 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn pipeline_matcher_progress() {
+        let qs = QuickSearchMatcher::new();
+        let mut matcher = PipelineMatcher::new(qs, &"amber".to_string().into_bytes());
+        matcher.print_progress = true;
+
+        let (in_tx, in_rx) = unbounded();
+        let (out_tx, out_rx) = unbounded();
+        thread::spawn(move || {
+            matcher.setup(0, in_rx, out_tx);
+        });
+
+        let _ = in_tx.send(PipelineInfo::SeqBeg(0));
+        for (i, path) in ["./src/ambs.rs", "./src/console.rs"].iter().enumerate() {
+            let size = std::fs::metadata(path).unwrap().len();
+            let _ = in_tx.send(PipelineInfo::SeqDat(
+                i,
+                PathInfo {
+                    path: PathBuf::from(path),
+                    size,
+                },
+            ));
+        }
+        let _ = in_tx.send(PipelineInfo::SeqEnd(2));
+
+        let mut matches = 0;
+        let mut last = None;
+        loop {
+            match out_rx.recv().unwrap() {
+                PipelineInfo::SeqDat(_, x) => matches += x.matches.len(),
+                PipelineInfo::MsgProgress(_, x) => last = Some(x),
+                PipelineInfo::SeqEnd(_) => break,
+                _ => (),
+            }
+        }
+
+        let last = last.unwrap();
+        assert_eq!(last.files_searched, 2);
+        assert_eq!(last.matches, matches);
+        assert!(last.bytes_scanned > 0);
+        assert!(last.current.is_none());
+    }
+
+    #[test]
+    fn pipeline_matcher_error() {
+        let qs = QuickSearchMatcher::new();
+        let mut matcher = PipelineMatcher::new(qs, &"amber".to_string().into_bytes());
+
+        let (in_tx, in_rx) = unbounded();
+        let (out_tx, out_rx) = unbounded();
+        thread::spawn(move || {
+            matcher.setup(0, in_rx, out_tx);
+        });
+
+        let _ = in_tx.send(PipelineInfo::SeqBeg(0));
+        let _ = in_tx.send(PipelineInfo::SeqDat(
+            0,
+            PathInfo {
+                path: PathBuf::from("./src/not_exist.rs"),
+                size: 0,
+            },
+        ));
+        let _ = in_tx.send(PipelineInfo::SeqEnd(1));
+
+        let mut errors = Vec::new();
+        loop {
+            match out_rx.recv().unwrap() {
+                PipelineInfo::MsgErr(_, x) => errors.push(x),
+                PipelineInfo::SeqEnd(_) => break,
+                _ => (),
+            }
+        }
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].stage, PipelineStage::Matcher);
+        assert_eq!(errors[0].kind, std::io::ErrorKind::NotFound);
+        assert_eq!(
+            format!("{}", errors[0]),
+            "Error: file not found @ \"./src/not_exist.rs\""
+        );
+    }
+
+    #[test]
+    fn pipeline_matcher_statistics() {
+        let qs = QuickSearchMatcher::new();
+        let mut matcher = PipelineMatcher::new(qs, &"amber".to_string().into_bytes());
+        matcher.mmap_bytes = 0;
+
+        let (in_tx, in_rx) = unbounded();
+        let (out_tx, out_rx) = unbounded();
+        thread::spawn(move || {
+            matcher.setup(0, in_rx, out_tx);
+        });
+
+        let _ = in_tx.send(PipelineInfo::SeqBeg(0));
+        for (i, path) in ["./src/ambs.rs", "./src/not_exist.rs"].iter().enumerate() {
+            let _ = in_tx.send(PipelineInfo::SeqDat(
+                i,
+                PathInfo {
+                    path: PathBuf::from(path),
+                    size: 0,
+                },
+            ));
+        }
+        let _ = in_tx.send(PipelineInfo::SeqEnd(2));
+
+        let mut matches = 0;
+        let mut statistics = None;
+        loop {
+            match out_rx.recv().unwrap() {
+                PipelineInfo::SeqDat(_, x) => matches += x.matches.len(),
+                PipelineInfo::MsgStat(_, x) => statistics = Some(x),
+                PipelineInfo::SeqEnd(_) => break,
+                _ => (),
+            }
+        }
+
+        let statistics = statistics.unwrap();
+        assert_eq!(statistics.files_searched, 1);
+        assert_eq!(statistics.mmap_files, 1);
+        assert_eq!(statistics.read_files, 0);
+        assert_eq!(statistics.matches, matches);
+        assert_eq!(statistics.bytes_read, std::fs::metadata("./src/ambs.rs").unwrap().len());
+    }
+
+    #[test]
+    fn pipeline_matcher_contents() {
+        let qs = QuickSearchMatcher::new();
+        let mut matcher = PipelineMatcher::new(qs, &"amber".to_string().into_bytes());
+        matcher.mmap_bytes = 0;
+
+        let (in_tx, in_rx) = unbounded();
+        let (out_tx, out_rx) = unbounded();
+        thread::spawn(move || {
+            matcher.setup(0, in_rx, out_tx);
+        });
+
+        let _ = in_tx.send(PipelineInfo::SeqBeg(0));
+        for (i, path) in ["./src/ambs.rs", "./LICENSE"].iter().enumerate() {
+            let _ = in_tx.send(PipelineInfo::SeqDat(
+                i,
+                PathInfo {
+                    path: PathBuf::from(path),
+                    size: 0,
+                },
+            ));
+        }
+        let _ = in_tx.send(PipelineInfo::SeqEnd(2));
+
+        let mut ret = Vec::new();
+        loop {
+            match out_rx.recv().unwrap() {
+                PipelineInfo::SeqDat(_, x) => ret.push(x),
+                PipelineInfo::SeqEnd(_) => break,
+                _ => (),
+            }
+        }
+
+        // The contents are kept only for the file with matches
+        let src = std::fs::read("./src/ambs.rs").unwrap();
+        let contents = ret[0].contents.as_ref().unwrap();
+        assert_eq!(contents.src(), &src[..]);
+        assert_eq!(contents.line_starts(), &Console::get_line_starts(&src)[..]);
+        assert!(ret[1].matches.is_empty());
+        assert!(ret[1].contents.is_none());
+    }
+
+    #[test]
+    fn pipeline_matcher_contents_budget() {
+        let qs = QuickSearchMatcher::new();
+        let mut matcher = PipelineMatcher::new(qs, &"amber".to_string().into_bytes());
+        let size = std::fs::metadata("./src/ambs.rs").unwrap().len() as usize;
+        let budget = Arc::new(ContentsBudget::new(size));
+        matcher.contents_budget = Some(budget.clone());
+
+        let (in_tx, in_rx) = unbounded();
+        let (out_tx, out_rx) = unbounded();
+        thread::spawn(move || {
+            matcher.setup(0, in_rx, out_tx);
+        });
+
+        let _ = in_tx.send(PipelineInfo::SeqBeg(0));
+        for (i, path) in ["./src/ambs.rs", "./src/ambr.rs"].iter().enumerate() {
+            let _ = in_tx.send(PipelineInfo::SeqDat(
+                i,
+                PathInfo {
+                    path: PathBuf::from(path),
+                    size: 0,
+                },
+            ));
+        }
+        let _ = in_tx.send(PipelineInfo::SeqEnd(2));
+
+        let mut ret = Vec::new();
+        loop {
+            match out_rx.recv().unwrap() {
+                PipelineInfo::SeqDat(_, x) => ret.push(x),
+                PipelineInfo::SeqEnd(_) => break,
+                _ => (),
+            }
+        }
+
+        // The contents over the budget are not kept while the former is held
+        assert!(ret[0].contents.is_some());
+        assert!(!ret[1].matches.is_empty());
+        assert!(ret[1].contents.is_none());
+        assert_eq!(budget.used.load(Ordering::SeqCst), size);
+
+        drop(ret);
+        assert_eq!(budget.used.load(Ordering::SeqCst), 0);
+    }
 }
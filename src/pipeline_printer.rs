@@ -1,29 +1,81 @@
-use crate::console::{Console, ConsoleTextKind};
-use crate::pipeline::{Pipeline, PipelineInfo};
-use crate::pipeline_matcher::PathMatch;
-use crate::util::{catch, decode_error};
+use crate::console::{ColumnUnit, Console, ConsoleStyles, ConsoleTextKind};
+use crate::format::{relative_path, FormatContext, HyperlinkFormat, Template};
+use crate::json::JsonEvent;
+use crate::pipeline::{Pipeline, PipelineError, PipelineInfo, PipelineNotice, PipelineStage};
+use crate::pipeline_matcher::{FileContents, PathMatch};
+use crate::util::catch;
 use crossbeam::channel::{Receiver, Sender};
-use memmap::Mmap;
-use std::fs::File;
+use std::env;
 use std::io::Error;
-use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, MutexGuard};
 use std::time::{Duration, Instant};
 
+// ---------------------------------------------------------------------------------------------------------------------
+// ContextLine
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// A line written around match lines by `-A`/`-B`/`-C`
+#[derive(Debug, PartialEq, Eq)]
+enum ContextLine {
+    /// The index of a context line
+    Line(usize),
+    /// The separator written where lines are omitted
+    Separator,
+}
+
 // ---------------------------------------------------------------------------------------------------------------------
 // PipelinePrinter
 // ---------------------------------------------------------------------------------------------------------------------
 
 pub struct PipelinePrinter {
     pub is_color: bool,
+    pub is_color_stderr: bool,
+    pub styles: ConsoleStyles,
     pub print_file: bool,
     pub print_column: bool,
     pub print_row: bool,
+    /// Unit of the column printed by `print_row`
+    pub column_unit: ColumnUnit,
+    /// Tab width for `ColumnUnit::Display`
+    pub tab_width: usize,
     pub print_line_by_match: bool,
-    pub infos: Vec<String>,
-    pub errors: Vec<String>,
+    /// Characters of text around matches kept in a line, where 0 means no limit
+    pub max_columns: usize,
+    /// Print matches of minified files trimmed by `max_columns` instead of a summary
+    pub max_columns_preview: bool,
+    /// Write paths as hyperlinks of OSC 8
+    pub hyperlink: Option<HyperlinkFormat>,
+    /// Print the path once as a heading of the matches of each file instead of at each line
+    pub print_heading: bool,
+    pub list_files: bool,
+    /// Print the number of matches of each file instead of the matches
+    pub print_count: bool,
+    /// Print the path of each file with matches instead of the matches
+    pub print_files_with_matches: bool,
+    /// Print nothing
+    pub quiet: bool,
+    /// Print only the matched part, one per line
+    pub only_matching: bool,
+    /// Capture group printed by `only_matching`, where 0 means the whole match
+    pub capture_group: usize,
+    /// Print `path:line:column:text` for each match, where the column is counted in characters from 1
+    pub print_vimgrep: bool,
+    /// Print each match by the template given by `--format`
+    pub format: Option<Template>,
+    pub print_progress: bool,
+    pub context_before: usize,
+    pub context_after: usize,
+    /// Print JSON Lines events instead of the text output
+    pub print_json: bool,
+    pub infos: Vec<PipelineNotice>,
+    pub errors: Vec<PipelineError>,
     console: Console,
     time_beg: Instant,
     time_bsy: Duration,
+    context_printed: bool,
+    heading_printed: bool,
+    current_dir: PathBuf,
 }
 
 impl Default for PipelinePrinter {
@@ -36,60 +88,179 @@ impl PipelinePrinter {
     pub fn new() -> Self {
         PipelinePrinter {
             is_color: true,
+            is_color_stderr: true,
+            styles: ConsoleStyles::default(),
             print_file: true,
             print_column: false,
             print_row: false,
+            column_unit: ColumnUnit::Byte,
+            tab_width: 8,
             print_line_by_match: false,
+            max_columns: 0,
+            max_columns_preview: false,
+            hyperlink: None,
+            print_heading: false,
+            list_files: false,
+            print_count: false,
+            print_files_with_matches: false,
+            quiet: false,
+            only_matching: false,
+            capture_group: 0,
+            print_vimgrep: false,
+            format: None,
+            print_progress: false,
+            context_before: 0,
+            context_after: 0,
+            print_json: false,
             infos: Vec::new(),
             errors: Vec::new(),
             console: Console::new(),
             time_beg: Instant::now(),
             time_bsy: Duration::new(0, 0),
+            context_printed: false,
+            heading_printed: false,
+            current_dir: env::current_dir().unwrap_or_default(),
         }
     }
 
     fn print_match(&mut self, pm: PathMatch) {
-        if pm.matches.is_empty() {
+        self.console.is_color = self.is_color;
+        self.console.is_color_stderr = self.is_color_stderr;
+        self.console.styles = self.styles;
+        self.console.max_columns = self.max_columns;
+
+        if self.list_files {
+            if !pm.skipped {
+                let lock = self.begin_record();
+                self.write_path(&pm.path, 1, 1);
+                self.console.write(ConsoleTextKind::Text, "\n");
+                self.end_record(lock);
+            }
+            return;
+        }
+
+        if pm.matches.is_empty() || self.quiet {
+            return;
+        }
+
+        if self.print_count || self.print_files_with_matches {
+            let lock = self.begin_record();
+            if self.print_files_with_matches || self.print_file {
+                self.write_path(&pm.path, 1, 1);
+            }
+            if self.print_count {
+                if self.print_file {
+                    self.console.write(ConsoleTextKind::Filename, ":");
+                }
+                self.console
+                    .write(ConsoleTextKind::Text, &format!("{}", pm.matches.len()));
+            }
+            self.console.write(ConsoleTextKind::Text, "\n");
+            self.end_record(lock);
             return;
         }
-        self.console.is_color = self.is_color;
+
+        let lock = self.begin_record();
 
         let result = catch::<_, (), Error>(|| {
-            let file = File::open(&pm.path)?;
-            let mmap = unsafe { Mmap::map(&file) }?;
-            let src = mmap.deref();
+            // The file is read again only if the matcher didn't keep it
+            let contents = match pm.contents {
+                Some(ref x) => x.clone(),
+                None => Arc::new(FileContents::open(&pm.path)?),
+            };
+            let src = contents.src();
+
+            if self.print_json {
+                self.print_json_match(&pm, src, contents.line_starts());
+                return Ok(());
+            }
+
+            if self.only_matching {
+                self.print_only_matching(&pm, src);
+                return Ok(());
+            }
+
+            if self.print_vimgrep {
+                self.print_vimgrep_match(&pm, src, contents.line_starts());
+                return Ok(());
+            }
+
+            if self.format.is_some() {
+                self.print_format_match(&pm, src, contents.line_starts());
+                return Ok(());
+            }
+
+            // The heading replaces the path of each line, and line numbers are always printed under it
+            let print_file = self.print_file && !self.print_heading;
+            let print_column = self.print_column || self.print_heading;
+            if self.print_heading {
+                self.write_heading(&pm.path);
+            }
+
+            if self.max_columns != 0 && !self.max_columns_preview && Console::is_minified(src) {
+                if print_file {
+                    self.write_path(&pm.path, 1, 1);
+                    self.console.write(ConsoleTextKind::Filename, ":");
+                }
+                self.console.write(
+                    ConsoleTextKind::Other,
+                    &format!("[Minified file omitted: {} matches]\n", pm.matches.len()),
+                );
+                return Ok(());
+            }
 
             let mut pos = 0;
             let mut column = 0;
-            let mut last_lf = 0;
+            let mut row_beg = 0;
             let mut last_line_beg = usize::MAX;
             let mut last_m_end = usize::MAX;
 
+            let print_context = self.context_before != 0 || self.context_after != 0;
+            // Line starts are needed only to find the context
+            let starts = if print_context { contents.line_starts() } else { &[] };
+            let mut last_line = None;
+            let mut printed_until = 0;
+
             if self.print_line_by_match {
                 for m in &pm.matches {
-                    if self.print_file {
-                        self.console.write(ConsoleTextKind::Filename, pm.path.to_str().unwrap());
-                        self.console.write(ConsoleTextKind::Filename, ":");
+                    if print_context {
+                        let line = Console::get_line_index(starts, m.beg);
+                        if last_line != Some(line) {
+                            self.write_context(&pm.path, src, starts, last_line, Some(line), &mut printed_until);
+                        }
+                        last_line = Some(Console::get_line_index(starts, m.end));
                     }
-                    if self.print_column | self.print_row {
+                    if print_column | self.print_row | self.hyperlink.is_some() {
                         while pos < m.beg {
                             if src[pos] == 0x0a {
                                 column += 1;
-                                last_lf = pos;
+                                row_beg = pos + 1;
                             }
                             pos += 1;
                         }
-                        if self.print_column {
-                            self.console.write(ConsoleTextKind::Other, &format!("{}:", column + 1));
-                        }
-                        if self.print_row {
-                            self.console
-                                .write(ConsoleTextKind::Other, &format!("{}:", m.beg - last_lf));
-                        }
+                    }
+                    let row = if self.print_row {
+                        self.column_unit.column(&src[row_beg..m.beg], self.tab_width)
+                    } else {
+                        0
+                    };
+                    let link_column = self.link_column(&src[row_beg..m.beg]);
+                    if print_file {
+                        self.write_path(&pm.path, column + 1, link_column);
+                        self.console.write(ConsoleTextKind::Filename, ":");
+                    }
+                    if print_column {
+                        self.write_line_number(&pm.path, column + 1, link_column, ":");
+                    }
+                    if self.print_row {
+                        self.console.write(ConsoleTextKind::Other, &format!("{}:", row));
                     }
 
                     self.console.write_match_line(src, m);
                 }
+                if print_context {
+                    self.write_context(&pm.path, src, starts, last_line, None, &mut printed_until);
+                }
             } else {
                 for m in &pm.matches {
                     let line_beg = Console::get_line_beg(src, m.beg);
@@ -99,26 +270,38 @@ impl PipelinePrinter {
                             let line_end = Console::get_line_end(src, last_m_end);
                             self.console.write_to_linebreak(src, last_m_end, line_end);
                         }
-
-                        if self.print_file {
-                            self.console.write(ConsoleTextKind::Filename, pm.path.to_str().unwrap());
-                            self.console.write(ConsoleTextKind::Filename, ":");
+                        if print_context {
+                            if last_m_end != usize::MAX {
+                                last_line = Some(Console::get_line_index(starts, last_m_end));
+                            }
+                            let line = Console::get_line_index(starts, m.beg);
+                            self.write_context(&pm.path, src, starts, last_line, Some(line), &mut printed_until);
                         }
-                        if self.print_column | self.print_row {
+
+                        if print_column | self.print_row | self.hyperlink.is_some() {
                             while pos < m.beg {
                                 if src[pos] == 0x0a {
                                     column += 1;
-                                    last_lf = pos;
+                                    row_beg = pos + 1;
                                 }
                                 pos += 1;
                             }
-                            if self.print_column {
-                                self.console.write(ConsoleTextKind::Other, &format!("{}:", column + 1));
-                            }
-                            if self.print_row {
-                                self.console
-                                    .write(ConsoleTextKind::Other, &format!("{}:", m.beg - last_lf));
-                            }
+                        }
+                        let row = if self.print_row {
+                            self.column_unit.column(&src[row_beg..m.beg], self.tab_width)
+                        } else {
+                            0
+                        };
+                        let link_column = self.link_column(&src[row_beg..m.beg]);
+                        if print_file {
+                            self.write_path(&pm.path, column + 1, link_column);
+                            self.console.write(ConsoleTextKind::Filename, ":");
+                        }
+                        if print_column {
+                            self.write_line_number(&pm.path, column + 1, link_column, ":");
+                        }
+                        if self.print_row {
+                            self.console.write(ConsoleTextKind::Other, &format!("{}:", row));
                         }
 
                         self.console.write_match_part(src, m, line_beg);
@@ -133,6 +316,10 @@ impl PipelinePrinter {
                 if last_m_end != usize::MAX {
                     let line_end = Console::get_line_end(src, last_m_end);
                     self.console.write_to_linebreak(src, last_m_end, line_end);
+                    if print_context {
+                        let line = Console::get_line_index(starts, last_m_end);
+                        self.write_context(&pm.path, src, starts, Some(line), None, &mut printed_until);
+                    }
                 }
             }
 
@@ -140,16 +327,282 @@ impl PipelinePrinter {
         });
         match result {
             Ok(_) => (),
-            Err(e) => self.console.write(
-                ConsoleTextKind::Error,
-                &format!("Error: {} @ {:?}\n", decode_error(e.kind()), pm.path),
-            ),
+            Err(e) => self
+                .errors
+                .push(PipelineError::new(PipelineStage::Printer, Some(&pm.path), &e)),
+        }
+        self.end_record(lock);
+    }
+
+    fn print_only_matching(&mut self, pm: &PathMatch, src: &[u8]) {
+        let mut pos = 0;
+        let mut column = 0;
+        let mut row_beg = 0;
+
+        for m in &pm.matches {
+            let (beg, end) = if self.capture_group == 0 {
+                (m.beg, m.end)
+            } else {
+                match m.sub_match.get(self.capture_group - 1) {
+                    Some(Some(x)) => (x.beg, x.end),
+                    _ => continue,
+                }
+            };
+            if beg == end {
+                continue;
+            }
+
+            if self.print_column | self.print_row | self.hyperlink.is_some() {
+                while pos < beg {
+                    if src[pos] == 0x0a {
+                        column += 1;
+                        row_beg = pos + 1;
+                    }
+                    pos += 1;
+                }
+            }
+            let row = if self.print_row {
+                self.column_unit.column(&src[row_beg..beg], self.tab_width)
+            } else {
+                0
+            };
+            if self.print_file {
+                self.write_path(&pm.path, column + 1, self.link_column(&src[row_beg..beg]));
+                self.console.write(ConsoleTextKind::Filename, ":");
+            }
+            if self.print_column {
+                self.console.write(ConsoleTextKind::Other, &format!("{}:", column + 1));
+            }
+            if self.print_row {
+                self.console.write(ConsoleTextKind::Other, &format!("{}:", row));
+            }
+
+            // A match across lines is printed as is
+            self.console
+                .write(ConsoleTextKind::MatchText, &String::from_utf8_lossy(&src[beg..end]));
+            self.console.write(ConsoleTextKind::Text, "\n");
+        }
+    }
+
+    fn print_vimgrep_match(&mut self, pm: &PathMatch, src: &[u8], starts: &[usize]) {
+        for m in &pm.matches {
+            let index = Console::get_line_index(starts, m.beg);
+            let (line_beg, line_end) = Console::get_line_range(src, starts, index);
+            let column = String::from_utf8_lossy(&src[line_beg..m.beg]).chars().count() + 1;
+
+            self.write_path(&pm.path, index + 1, column);
+            self.console.write(ConsoleTextKind::Filename, ":");
+            self.console
+                .write(ConsoleTextKind::Other, &format!("{}:{}:", index + 1, column));
+
+            // Only the first line of a match across lines is printed
+            let match_end = m.end.min(line_end).max(m.beg);
+            self.console
+                .write(ConsoleTextKind::Text, &String::from_utf8_lossy(&src[line_beg..m.beg]));
+            self.console.write(
+                ConsoleTextKind::MatchText,
+                &String::from_utf8_lossy(&src[m.beg..match_end]),
+            );
+            self.console.write_to_linebreak(src, match_end, line_end);
+        }
+    }
+
+    fn print_format_match(&mut self, pm: &PathMatch, src: &[u8], starts: &[usize]) {
+        let relpath = relative_path(&pm.path, &self.current_dir);
+
+        for m in &pm.matches {
+            let index = Console::get_line_index(starts, m.beg);
+            let line_range = Console::get_line_range(src, starts, index);
+            let ctx = FormatContext {
+                path: &pm.path,
+                relpath,
+                src,
+                m,
+                line: index + 1,
+                column: self.column_unit.column(&src[line_range.0..m.beg], self.tab_width),
+                line_range,
+                count: pm.matches.len(),
+            };
+            let parts = match self.format {
+                Some(ref format) => format.render(&ctx),
+                None => return,
+            };
+            for (kind, text) in parts {
+                self.console.write(kind, &text);
+            }
+            self.console.write(ConsoleTextKind::Text, "\n");
+        }
+    }
+
+    fn print_json_match(&mut self, pm: &PathMatch, src: &[u8], starts: &[usize]) {
+        let path = pm.path.to_string_lossy();
+
+        let mut out = JsonEvent::Begin { path: path.clone() }.to_line();
+        for m in &pm.matches {
+            out.push_str(&JsonEvent::from_match(&pm.path, src, starts, m).to_line());
+        }
+        out.push_str(
+            &JsonEvent::End {
+                path,
+                matches: pm.matches.len(),
+            }
+            .to_line(),
+        );
+        self.console.write(ConsoleTextKind::Text, &out);
+    }
+
+    /// Write the lines after the match line `last`, and the lines before the match line `next`.
+    /// Lines before `printed_until` are already written, and a separator is written if some lines are omitted.
+    fn write_context(
+        &mut self,
+        path: &Path,
+        src: &[u8],
+        starts: &[usize],
+        last: Option<usize>,
+        next: Option<usize>,
+        printed_until: &mut usize,
+    ) {
+        let lines = PipelinePrinter::context_lines(
+            self.context_before,
+            self.context_after,
+            starts.len(),
+            last,
+            next,
+            printed_until,
+            &mut self.context_printed,
+        );
+        for x in lines {
+            match x {
+                ContextLine::Line(i) => self.write_context_line(path, src, starts, i),
+                ContextLine::Separator => self.console.write(ConsoleTextKind::Other, "--\n"),
+            }
+        }
+    }
+
+    /// Get the lines written by `write_context` in a file of `line_num` lines.
+    /// `printed` is set if any context is written, and then the next context is separated even in another file.
+    fn context_lines(
+        before: usize,
+        after: usize,
+        line_num: usize,
+        last: Option<usize>,
+        next: Option<usize>,
+        printed_until: &mut usize,
+        printed: &mut bool,
+    ) -> Vec<ContextLine> {
+        let mut ret = Vec::new();
+        if let Some(last) = last {
+            let end = (last + 1 + after).min(next.unwrap_or(line_num)).min(line_num);
+            ret.extend((last + 1..end).map(ContextLine::Line));
+            *printed_until = end.max(last + 1);
+        }
+
+        if let Some(next) = next {
+            let beg = next.saturating_sub(before).max(*printed_until);
+            if *printed && (last.is_none() || beg > *printed_until) {
+                ret.push(ContextLine::Separator);
+            }
+            ret.extend((beg..next).map(ContextLine::Line));
+            *printed = true;
+        }
+        ret
+    }
+
+    /// Write the path, which is a hyperlink to the line and column if `hyperlink` is set.
+    fn write_path(&mut self, path: &Path, line: usize, column: usize) {
+        let text = path.to_string_lossy();
+        match self.hyperlink {
+            Some(ref hyperlink) => {
+                let url = hyperlink.url(&self.current_dir.join(path), line, column);
+                self.console.write_hyperlink(ConsoleTextKind::Filename, &text, &url);
+            }
+            None => self.console.write(ConsoleTextKind::Filename, &text),
+        }
+    }
+
+    /// Write the line number followed by `sep`, which is a hyperlink instead of the path under the heading.
+    fn write_line_number(&mut self, path: &Path, line: usize, column: usize, sep: &str) {
+        let text = line.to_string();
+        match self.hyperlink {
+            Some(ref hyperlink) if self.print_heading => {
+                let url = hyperlink.url(&self.current_dir.join(path), line, column);
+                self.console.write_hyperlink(ConsoleTextKind::Other, &text, &url);
+            }
+            _ => self.console.write(ConsoleTextKind::Other, &text),
+        }
+        self.console.write(ConsoleTextKind::Other, sep);
+    }
+
+    /// Get the column of a hyperlink following `prefix`, which is counted in characters as `--vimgrep` for editors.
+    fn link_column(&self, prefix: &[u8]) -> usize {
+        if self.hyperlink.is_some() {
+            ColumnUnit::Char.column(prefix, self.tab_width)
+        } else {
+            0
+        }
+    }
+
+    /// Write the path as the heading of the matches of a file, following a blank line after the previous file.
+    fn write_heading(&mut self, path: &Path) {
+        if self.heading_printed {
+            self.console.write(ConsoleTextKind::Text, "\n");
+        }
+        self.write_path(path, 1, 1);
+        self.console.write(ConsoleTextKind::Text, "\n");
+        self.heading_printed = true;
+        // Files are separated by the blank line instead of "--"
+        self.context_printed = false;
+    }
+
+    fn write_context_line(&mut self, path: &Path, src: &[u8], starts: &[usize], index: usize) {
+        if self.print_file && !self.print_heading {
+            self.write_path(path, index + 1, 1);
+            self.console.write(ConsoleTextKind::Filename, "-");
+        }
+        if self.print_column || self.print_heading {
+            self.write_line_number(path, index + 1, 1, "-");
+        }
+        let (beg, end) = Console::get_line_range(src, starts, index);
+        self.console.write_to_linebreak(src, beg, end);
+    }
+
+    /// Send the notices and the errors kept until the end, and the end of the sequence `x`.
+    fn send_end(&mut self, id: usize, tx: &Sender<PipelineInfo<usize>>, x: usize) {
+        for i in &self.infos {
+            let _ = tx.send(PipelineInfo::MsgInfo(id, i.clone()));
+        }
+        for e in &self.errors {
+            let _ = tx.send(PipelineInfo::MsgErr(id, e.clone()));
+        }
+
+        let _ = tx.send(PipelineInfo::MsgTime(id, self.time_bsy, self.time_beg.elapsed()));
+        let _ = tx.send(PipelineInfo::SeqEnd(x));
+    }
+
+    /// Clear the progress line, and hold the output until the record is written.
+    /// JSON events are also held because main writes error events to stdout.
+    fn begin_record(&mut self) -> Option<MutexGuard<'static, ()>> {
+        if self.print_progress || self.print_json {
+            let lock = Console::lock();
+            if self.print_progress {
+                self.console.write_with_clear(ConsoleTextKind::Info, "");
+            }
+            Some(lock)
+        } else {
+            None
+        }
+    }
+
+    fn end_record(&mut self, lock: Option<MutexGuard<'static, ()>>) {
+        if lock.is_some() {
+            self.console.flush();
         }
     }
 }
 
-impl Pipeline<PathMatch, ()> for PipelinePrinter {
-    fn setup(&mut self, id: usize, rx: Receiver<PipelineInfo<PathMatch>>, tx: Sender<PipelineInfo<()>>) {
+/// The number of matches of each file is sent to the next stage
+impl Pipeline<PathMatch, usize> for PipelinePrinter {
+    fn setup(&mut self, id: usize, rx: Receiver<PipelineInfo<PathMatch>>, tx: Sender<PipelineInfo<usize>>) {
         self.infos = Vec::new();
         self.errors = Vec::new();
         let mut seq_beg_arrived = false;
@@ -158,9 +611,19 @@ impl Pipeline<PathMatch, ()> for PipelinePrinter {
             match rx.recv() {
                 Ok(PipelineInfo::SeqDat(x, pm)) => {
                     watch_time!(self.time_bsy, {
+                        let matches = pm.matches.len();
                         self.print_match(pm);
-                        let _ = tx.send(PipelineInfo::SeqDat(x, ()));
+                        // Errors are sent without waiting for the end, so they are shown along with the output
+                        for e in self.errors.drain(..) {
+                            let _ = tx.send(PipelineInfo::MsgErr(id, e));
+                        }
+                        let _ = tx.send(PipelineInfo::SeqDat(x, matches));
                     });
+                    // Nothing is shown after the reader of the output quits, so the following files are not waited
+                    if Console::is_closed() {
+                        self.send_end(id, &tx, x);
+                        break;
+                    }
                 }
 
                 Ok(PipelineInfo::SeqBeg(x)) => {
@@ -172,15 +635,7 @@ impl Pipeline<PathMatch, ()> for PipelinePrinter {
                 }
 
                 Ok(PipelineInfo::SeqEnd(x)) => {
-                    for i in &self.infos {
-                        let _ = tx.send(PipelineInfo::MsgInfo(id, i.clone()));
-                    }
-                    for e in &self.errors {
-                        let _ = tx.send(PipelineInfo::MsgErr(id, e.clone()));
-                    }
-
-                    let _ = tx.send(PipelineInfo::MsgTime(id, self.time_bsy, self.time_beg.elapsed()));
-                    let _ = tx.send(PipelineInfo::SeqEnd(x));
+                    self.send_end(id, &tx, x);
                     break;
                 }
 
@@ -196,8 +651,94 @@ impl Pipeline<PathMatch, ()> for PipelinePrinter {
                 Ok(PipelineInfo::MsgTime(i, t0, t1)) => {
                     let _ = tx.send(PipelineInfo::MsgTime(i, t0, t1));
                 }
+                Ok(PipelineInfo::MsgProgress(i, x)) => {
+                    let _ = tx.send(PipelineInfo::MsgProgress(i, x));
+                }
+                Ok(PipelineInfo::MsgStat(i, x)) => {
+                    let _ = tx.send(PipelineInfo::MsgStat(i, x));
+                }
                 Err(_) => break,
             }
         }
     }
 }
+
+// ---------------------------------------------------------------------------------------------------------------------
+// Test
+// ---------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(ret: &mut Vec<String>, lines: Vec<ContextLine>) {
+        for x in lines {
+            match x {
+                ContextLine::Line(i) => ret.push(format!("{}", i)),
+                ContextLine::Separator => ret.push(String::from("--")),
+            }
+        }
+    }
+
+    /// Render the lines of files as `print_match` does, where a match line is in brackets.
+    fn context(before: usize, after: usize, files: &[(usize, &[usize])]) -> String {
+        let mut ret = Vec::new();
+        let mut printed = false;
+        for (line_num, matches) in files {
+            let mut last = None;
+            let mut printed_until = 0;
+            for m in matches.iter() {
+                if last != Some(*m) {
+                    push(
+                        &mut ret,
+                        PipelinePrinter::context_lines(
+                            before,
+                            after,
+                            *line_num,
+                            last,
+                            Some(*m),
+                            &mut printed_until,
+                            &mut printed,
+                        ),
+                    );
+                    ret.push(format!("[{}]", m));
+                }
+                last = Some(*m);
+            }
+            push(
+                &mut ret,
+                PipelinePrinter::context_lines(before, after, *line_num, last, None, &mut printed_until, &mut printed),
+            );
+        }
+        ret.join(" ")
+    }
+
+    #[test]
+    fn context_separated() {
+        assert_eq!(context(1, 1, &[(20, &[2, 10])]), "1 [2] 3 -- 9 [10] 11");
+        assert_eq!(context(0, 1, &[(20, &[2, 10])]), "[2] 3 -- [10] 11");
+        assert_eq!(context(1, 0, &[(20, &[2, 10])]), "1 [2] -- 9 [10]");
+    }
+
+    #[test]
+    fn context_overlapped() {
+        assert_eq!(context(2, 2, &[(20, &[5, 7])]), "3 4 [5] 6 [7] 8 9");
+        assert_eq!(context(2, 2, &[(20, &[5, 6])]), "3 4 [5] [6] 7 8");
+        assert_eq!(context(3, 0, &[(20, &[5, 7])]), "2 3 4 [5] 6 [7]");
+        assert_eq!(context(0, 3, &[(20, &[5, 7])]), "[5] 6 [7] 8 9 10");
+    }
+
+    #[test]
+    fn context_adjacent() {
+        // The after context of 2 ends at 4, and the before context of 7 begins at 5
+        assert_eq!(context(2, 2, &[(20, &[2, 7])]), "0 1 [2] 3 4 5 6 [7] 8 9");
+        // One line is omitted between them
+        assert_eq!(context(2, 2, &[(20, &[2, 8])]), "0 1 [2] 3 4 -- 6 7 [8] 9 10");
+    }
+
+    #[test]
+    fn context_file_boundary() {
+        assert_eq!(context(3, 3, &[(20, &[0, 19])]), "[0] 1 2 3 -- 16 17 18 [19]");
+        assert_eq!(context(1, 1, &[(2, &[1]), (3, &[0])]), "0 [1] -- [0] 1");
+    }
+}
@@ -1,19 +1,17 @@
-use crate::console::{Console, ConsoleTextKind};
-use crate::pipeline::{Pipeline, PipelineInfo};
-use crate::pipeline_matcher::PathMatch;
-use crate::util::{catch, decode_error, exit};
+use crate::console::{ColumnUnit, Console, ConsoleStyles, ConsoleTextKind};
+use crate::pipeline::{Pipeline, PipelineError, PipelineInfo, PipelineNotice, PipelineStage};
+use crate::pipeline_matcher::{FileContents, PathMatch};
+use crate::util::{catch, exit};
 use crossbeam::channel::{Receiver, Sender};
-use ctrlc;
 use filetime::FileTime;
 use getch::Getch;
-use memmap::Mmap;
 use regex::Regex;
 use std::collections::HashSet;
-use std::fs::{self, File};
+use std::fs;
 use std::io::{Error, Write};
-use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tempfile::NamedTempFile;
 use unicode_width::UnicodeWidthStr;
@@ -24,13 +22,26 @@ use unicode_width::UnicodeWidthStr;
 
 pub struct PipelineReplacer {
     pub is_color: bool,
+    pub is_color_stderr: bool,
+    pub styles: ConsoleStyles,
     pub is_interactive: bool,
     pub preserve_time: bool,
     pub print_file: bool,
     pub print_column: bool,
     pub print_row: bool,
-    pub infos: Vec<String>,
-    pub errors: Vec<String>,
+    /// Unit of the column printed by `print_row`
+    pub column_unit: ColumnUnit,
+    /// Tab width for `ColumnUnit::Display`
+    pub tab_width: usize,
+    /// Characters of text around matches kept in a line of the interactive prompt, where 0 means no limit
+    pub max_columns: usize,
+    /// Lines shown before and after the match in the interactive prompt
+    pub context_before: usize,
+    pub context_after: usize,
+    /// The temporary file being written, which should be removed if the replacement is cancelled
+    pub tmpfile_path: Arc<Mutex<Option<PathBuf>>>,
+    pub infos: Vec<PipelineNotice>,
+    pub errors: Vec<PipelineError>,
     console: Console,
     all_replace: bool,
     keyword: Vec<u8>,
@@ -45,11 +56,19 @@ impl PipelineReplacer {
     pub fn new(keyword: &[u8], replacement: &[u8], regex: bool) -> Self {
         PipelineReplacer {
             is_color: true,
+            is_color_stderr: true,
+            styles: ConsoleStyles::default(),
             is_interactive: true,
             preserve_time: false,
             print_file: true,
             print_column: false,
             print_row: false,
+            column_unit: ColumnUnit::Byte,
+            tab_width: 8,
+            max_columns: 0,
+            context_before: 0,
+            context_after: 0,
+            tmpfile_path: Arc::new(Mutex::new(None)),
             infos: Vec::new(),
             errors: Vec::new(),
             console: Console::new(),
@@ -63,7 +82,7 @@ impl PipelineReplacer {
         }
     }
 
-    fn replace_match(&mut self, pm: PathMatch) {
+    fn replace_match(&mut self, mut pm: PathMatch) {
         if pm.matches.is_empty() {
             return;
         }
@@ -80,35 +99,33 @@ impl PipelineReplacer {
         }
 
         self.console.is_color = self.is_color;
+        self.console.is_color_stderr = self.is_color_stderr;
+        self.console.styles = self.styles;
+        self.console.max_columns = self.max_columns;
 
         let result = catch::<_, (), Error>(|| {
             let mut tmpfile = NamedTempFile::new_in(pm.path.parent().unwrap_or(&pm.path))?;
 
-            let tmpfile_path = tmpfile.path().to_path_buf();
+            *self.tmpfile_path.lock().unwrap() = Some(tmpfile.path().to_path_buf());
             #[cfg(not(windows))]
             let c_lflag = crate::util::get_c_lflag();
-            let _ = ctrlc::set_handler(move || {
-                let path = tmpfile_path.clone();
-                let mut console = Console::new();
-                console.write(
-                    ConsoleTextKind::Info,
-                    &format!("\nCleanup temporary file: {:?}\n", path),
-                );
-                let _ = fs::remove_file(path);
-                #[cfg(not(windows))]
-                crate::util::set_c_lflag(c_lflag);
-                exit(0, &mut console);
-            });
 
             {
-                let file = File::open(&pm.path)?;
-                let mmap = unsafe { Mmap::map(&file) }?;
-                let src = mmap.deref();
+                // The contents are dropped at the end of this block, so that the file can be replaced on Windows
+                let contents = match pm.contents.take() {
+                    Some(x) => x,
+                    None => Arc::new(FileContents::open(&pm.path)?),
+                };
+                let src = contents.src();
+
+                let print_context = self.is_interactive && (self.context_before != 0 || self.context_after != 0);
+                // Line starts are needed only to find the context
+                let starts = if print_context { contents.line_starts() } else { &[] };
 
                 let mut i = 0;
                 let mut pos = 0;
                 let mut column = 0;
-                let mut last_lf = 0;
+                let mut row_beg = 0;
                 for m in &pm.matches {
                     tmpfile.write_all(&src[i..m.beg])?;
 
@@ -120,18 +137,25 @@ impl PipelineReplacer {
 
                     let mut do_replace = true;
                     if self.is_interactive & !self.all_replace {
+                        if print_context {
+                            let line = Console::get_line_index(starts, m.beg);
+                            for j in line.saturating_sub(self.context_before)..line {
+                                self.write_context_line(&pm.path, src, starts, j);
+                            }
+                        }
+
                         let mut header_width = 0;
                         if self.print_file {
-                            let path = pm.path.to_str().unwrap();
-                            header_width += UnicodeWidthStr::width(path) + 2;
-                            self.console.write(ConsoleTextKind::Filename, path);
+                            let path = pm.path.to_string_lossy();
+                            header_width += UnicodeWidthStr::width(&*path) + 2;
+                            self.console.write(ConsoleTextKind::Filename, &path);
                             self.console.write(ConsoleTextKind::Other, ": ");
                         }
                         if self.print_column | self.print_row {
                             while pos < m.beg {
                                 if src[pos] == 0x0a {
                                     column += 1;
-                                    last_lf = pos;
+                                    row_beg = pos + 1;
                                 }
                                 pos += 1;
                             }
@@ -141,7 +165,8 @@ impl PipelineReplacer {
                                 self.console.write(ConsoleTextKind::Other, &column_str);
                             }
                             if self.print_row {
-                                let row_str = format!("{}:", m.beg - last_lf);
+                                let row_str =
+                                    format!("{}:", self.column_unit.column(&src[row_beg..m.beg], self.tab_width));
                                 header_width += row_str.width();
                                 self.console.write(ConsoleTextKind::Other, &row_str);
                             }
@@ -158,6 +183,14 @@ impl PipelineReplacer {
                             .write(ConsoleTextKind::Other, &format!("{} -> ", " ".repeat(header_width - 4)));
                         self.console.write_replace_line(src, m, &replacement);
 
+                        if print_context {
+                            let line = Console::get_line_index(starts, m.end);
+                            let end = (line + 1 + self.context_after).min(starts.len());
+                            for j in line + 1..end {
+                                self.write_context_line(&pm.path, src, starts, j);
+                            }
+                        }
+
                         let getch = Getch::new();
                         loop {
                             self.console
@@ -220,15 +253,27 @@ impl PipelineReplacer {
 
             Ok(())
         });
+        *self.tmpfile_path.lock().unwrap() = None;
         match result {
             Ok(_) => (),
-            Err(e) => self.console.write(
-                ConsoleTextKind::Error,
-                &format!("Error: {} @ {:?}\n", decode_error(e.kind()), pm.path),
-            ),
+            Err(e) => self
+                .errors
+                .push(PipelineError::new(PipelineStage::Replacer, Some(&pm.path), &e)),
         }
     }
 
+    fn write_context_line(&mut self, path: &Path, src: &[u8], starts: &[usize], index: usize) {
+        if self.print_file {
+            self.console.write(ConsoleTextKind::Filename, &path.to_string_lossy());
+            self.console.write(ConsoleTextKind::Other, "- ");
+        }
+        if self.print_column {
+            self.console.write(ConsoleTextKind::Other, &format!("{}-", index + 1));
+        }
+        let (beg, end) = Console::get_line_range(src, starts, index);
+        self.console.write_to_linebreak(src, beg, end);
+    }
+
     fn get_regex_replacement(&self, org: &[u8]) -> Vec<u8> {
         // All unwrap() is safe because keyword is already matched in pipeline_matcher
         let org = str::from_utf8(org).unwrap();
@@ -247,8 +292,9 @@ impl PipelineReplacer {
     }
 }
 
-impl Pipeline<PathMatch, ()> for PipelineReplacer {
-    fn setup(&mut self, id: usize, rx: Receiver<PipelineInfo<PathMatch>>, tx: Sender<PipelineInfo<()>>) {
+/// The number of matches of each file is sent to the next stage
+impl Pipeline<PathMatch, usize> for PipelineReplacer {
+    fn setup(&mut self, id: usize, rx: Receiver<PipelineInfo<PathMatch>>, tx: Sender<PipelineInfo<usize>>) {
         self.infos = Vec::new();
         self.errors = Vec::new();
         let mut seq_beg_arrived = false;
@@ -257,8 +303,13 @@ impl Pipeline<PathMatch, ()> for PipelineReplacer {
             match rx.recv() {
                 Ok(PipelineInfo::SeqDat(x, pm)) => {
                     watch_time!(self.time_bsy, {
+                        let matches = pm.matches.len();
                         self.replace_match(pm);
-                        let _ = tx.send(PipelineInfo::SeqDat(x, ()));
+                        // Errors are sent without waiting for the end, so they are shown along with the output
+                        for e in self.errors.drain(..) {
+                            let _ = tx.send(PipelineInfo::MsgErr(id, e));
+                        }
+                        let _ = tx.send(PipelineInfo::SeqDat(x, matches));
                     });
                 }
 
@@ -295,6 +346,12 @@ impl Pipeline<PathMatch, ()> for PipelineReplacer {
                 Ok(PipelineInfo::MsgTime(i, t0, t1)) => {
                     let _ = tx.send(PipelineInfo::MsgTime(i, t0, t1));
                 }
+                Ok(PipelineInfo::MsgProgress(i, x)) => {
+                    let _ = tx.send(PipelineInfo::MsgProgress(i, x));
+                }
+                Ok(PipelineInfo::MsgStat(i, x)) => {
+                    let _ = tx.send(PipelineInfo::MsgStat(i, x));
+                }
                 Err(_) => break,
             }
         }
@@ -1,18 +1,63 @@
-use crate::pipeline::{PipelineInfo, PipelineJoin};
+use crate::pipeline::{PipelineError, PipelineInfo, PipelineJoin, PipelineNotice, SeqGate};
 use crate::pipeline_matcher::PathMatch;
-use crossbeam::channel::{Receiver, Sender};
+use crossbeam::channel::{Receiver, Select, Sender};
+use std::cmp::Reverse;
 use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use std::fs;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+// ---------------------------------------------------------------------------------------------------------------------
+// SortKey
+// ---------------------------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// The order found by the finder
+    None,
+    /// The path order, which is given by the finder walking each directory in name order
+    Path,
+    /// The modification time of the file
+    Mtime,
+    /// The number of matches in the file
+    Count,
+}
+
+impl SortKey {
+    /// Whether all results must be buffered before sorting
+    pub fn is_buffered(&self) -> bool {
+        matches!(self, SortKey::Mtime | SortKey::Count)
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SortKey::None),
+            "path" => Ok(SortKey::Path),
+            "mtime" => Ok(SortKey::Mtime),
+            "count" => Ok(SortKey::Count),
+            _ => Err(format!("invalid sort key: {}", s)),
+        }
+    }
+}
 
 // ---------------------------------------------------------------------------------------------------------------------
 // PipelineSorter
 // ---------------------------------------------------------------------------------------------------------------------
 
 pub struct PipelineSorter {
-    pub infos: Vec<String>,
-    pub errors: Vec<String>,
+    pub infos: Vec<PipelineNotice>,
+    pub errors: Vec<PipelineError>,
     pub through: bool,
+    pub gate: Option<Arc<SeqGate>>,
+    pub sort_key: SortKey,
+    pub sort_reverse: bool,
     map: HashMap<usize, PathMatch>,
+    sorted: Vec<(u128, usize, PathMatch)>,
     seq_no: usize,
     join_num: usize,
     time_beg: Instant,
@@ -25,13 +70,45 @@ impl PipelineSorter {
             infos: Vec::new(),
             errors: Vec::new(),
             through: false,
+            gate: None,
+            sort_key: SortKey::None,
+            sort_reverse: false,
             map: HashMap::new(),
+            sorted: Vec::new(),
             seq_no: 0,
             join_num: num,
             time_beg: Instant::now(),
             time_bsy: Duration::new(0, 0),
         }
     }
+
+    fn output(&mut self, seq: usize, pm: PathMatch, tx: &Sender<PipelineInfo<PathMatch>>) {
+        let key = match self.sort_key {
+            SortKey::None | SortKey::Path => {
+                let _ = tx.send(PipelineInfo::SeqDat(seq, pm));
+                return;
+            }
+            SortKey::Mtime => fs::metadata(&pm.path)
+                .and_then(|x| x.modified())
+                .ok()
+                .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |x| x.as_nanos()),
+            SortKey::Count => pm.matches.len() as u128,
+        };
+        self.sorted.push((key, seq, pm));
+    }
+
+    fn flush_sorted(&mut self, tx: &Sender<PipelineInfo<PathMatch>>) {
+        // The stable sort keeps the found order of the same keys
+        if self.sort_reverse {
+            self.sorted.sort_by_key(|x| Reverse(x.0));
+        } else {
+            self.sorted.sort_by_key(|x| x.0);
+        }
+        for (_, seq, pm) in self.sorted.drain(..) {
+            let _ = tx.send(PipelineInfo::SeqDat(seq, pm));
+        }
+    }
 }
 
 impl PipelineJoin<PathMatch, PathMatch> for PipelineSorter {
@@ -41,70 +118,86 @@ impl PipelineJoin<PathMatch, PathMatch> for PipelineSorter {
         let mut seq_beg_arrived = false;
         let mut end_num = 0;
 
-        loop {
-            for rx in &rx {
-                match rx.recv() {
-                    Ok(PipelineInfo::SeqDat(x, p)) => {
-                        watch_time!(self.time_bsy, {
-                            if self.through {
-                                let _ = tx.send(PipelineInfo::SeqDat(x, p));
-                            } else {
-                                self.map.insert(x, p);
-                                loop {
-                                    if !self.map.contains_key(&self.seq_no) {
-                                        break;
-                                    }
-                                    {
-                                        let ret = self.map.get(&self.seq_no).unwrap();
-                                        let _ = tx.send(PipelineInfo::SeqDat(self.seq_no, ret.clone()));
-                                    }
-                                    let _ = self.map.remove(&self.seq_no);
-                                    self.seq_no += 1;
-                                }
-                            }
-                        });
-                    }
-
-                    Ok(PipelineInfo::SeqBeg(x)) => {
-                        if !seq_beg_arrived {
-                            self.seq_no = x;
-                            self.time_beg = Instant::now();
-                            let _ = tx.send(PipelineInfo::SeqBeg(x));
-                            seq_beg_arrived = true;
-                        }
-                    }
+        let mut select = Select::new();
+        for rx in &rx {
+            select.recv(rx);
+        }
+        let mut live_num = rx.len();
 
-                    Ok(PipelineInfo::SeqEnd(x)) => {
-                        end_num += 1;
-                        if end_num != self.join_num {
-                            continue;
+        while live_num != 0 {
+            let oper = select.select();
+            let index = oper.index();
+            match oper.recv(&rx[index]) {
+                Ok(PipelineInfo::SeqDat(x, p)) => {
+                    watch_time!(self.time_bsy, {
+                        if self.through {
+                            self.output(x, p, &tx);
+                        } else {
+                            self.map.insert(x, p);
+                            while let Some(ret) = self.map.remove(&self.seq_no) {
+                                self.output(self.seq_no, ret, &tx);
+                                self.seq_no += 1;
+                            }
+                            if let Some(ref gate) = self.gate {
+                                gate.advance(self.seq_no);
+                            }
                         }
+                    });
+                }
 
-                        for i in &self.infos {
-                            let _ = tx.send(PipelineInfo::MsgInfo(id, i.clone()));
-                        }
-                        for e in &self.errors {
-                            let _ = tx.send(PipelineInfo::MsgErr(id, e.clone()));
+                Ok(PipelineInfo::SeqBeg(x)) => {
+                    if !seq_beg_arrived {
+                        self.seq_no = x;
+                        if let Some(ref gate) = self.gate {
+                            gate.advance(x);
                         }
-
-                        let _ = tx.send(PipelineInfo::MsgTime(id, self.time_bsy, self.time_beg.elapsed()));
-                        let _ = tx.send(PipelineInfo::SeqEnd(x));
-                        break;
+                        self.time_beg = Instant::now();
+                        let _ = tx.send(PipelineInfo::SeqBeg(x));
+                        seq_beg_arrived = true;
                     }
+                }
 
-                    Ok(PipelineInfo::MsgDebug(i, e)) => {
-                        let _ = tx.send(PipelineInfo::MsgDebug(i, e));
-                    }
-                    Ok(PipelineInfo::MsgInfo(i, e)) => {
-                        let _ = tx.send(PipelineInfo::MsgInfo(i, e));
+                Ok(PipelineInfo::SeqEnd(x)) => {
+                    end_num += 1;
+                    if end_num != self.join_num {
+                        continue;
                     }
-                    Ok(PipelineInfo::MsgErr(i, e)) => {
-                        let _ = tx.send(PipelineInfo::MsgErr(i, e));
+
+                    self.flush_sorted(&tx);
+
+                    for i in &self.infos {
+                        let _ = tx.send(PipelineInfo::MsgInfo(id, i.clone()));
                     }
-                    Ok(PipelineInfo::MsgTime(i, t0, t1)) => {
-                        let _ = tx.send(PipelineInfo::MsgTime(i, t0, t1));
+                    for e in &self.errors {
+                        let _ = tx.send(PipelineInfo::MsgErr(id, e.clone()));
                     }
-                    Err(_) => break,
+
+                    let _ = tx.send(PipelineInfo::MsgTime(id, self.time_bsy, self.time_beg.elapsed()));
+                    let _ = tx.send(PipelineInfo::SeqEnd(x));
+                    break;
+                }
+
+                Ok(PipelineInfo::MsgDebug(i, e)) => {
+                    let _ = tx.send(PipelineInfo::MsgDebug(i, e));
+                }
+                Ok(PipelineInfo::MsgInfo(i, e)) => {
+                    let _ = tx.send(PipelineInfo::MsgInfo(i, e));
+                }
+                Ok(PipelineInfo::MsgErr(i, e)) => {
+                    let _ = tx.send(PipelineInfo::MsgErr(i, e));
+                }
+                Ok(PipelineInfo::MsgTime(i, t0, t1)) => {
+                    let _ = tx.send(PipelineInfo::MsgTime(i, t0, t1));
+                }
+                Ok(PipelineInfo::MsgProgress(i, x)) => {
+                    let _ = tx.send(PipelineInfo::MsgProgress(i, x));
+                }
+                Ok(PipelineInfo::MsgStat(i, x)) => {
+                    let _ = tx.send(PipelineInfo::MsgStat(i, x));
+                }
+                Err(_) => {
+                    select.remove(index);
+                    live_num -= 1;
                 }
             }
         }
@@ -118,6 +211,7 @@ impl PipelineJoin<PathMatch, PathMatch> for PipelineSorter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::matcher::Match;
     use crate::pipeline::{PipelineInfo, PipelineJoin};
     use crate::pipeline_matcher::PathMatch;
     use crossbeam::channel::unbounded;
@@ -127,6 +221,8 @@ mod tests {
     #[test]
     fn pipeline_sorter() {
         let mut sorter = PipelineSorter::new(1);
+        let gate = Arc::new(SeqGate::new(4));
+        sorter.gate = Some(gate.clone());
 
         let (in_tx, in_rx) = unbounded();
         let (out_tx, out_rx) = unbounded();
@@ -140,6 +236,8 @@ mod tests {
             PathMatch {
                 path: PathBuf::from("./"),
                 matches: Vec::new(),
+                skipped: false,
+                contents: None,
             },
         ));
         let _ = in_tx.send(PipelineInfo::SeqDat(
@@ -147,6 +245,8 @@ mod tests {
             PathMatch {
                 path: PathBuf::from("./"),
                 matches: Vec::new(),
+                skipped: false,
+                contents: None,
             },
         ));
         let _ = in_tx.send(PipelineInfo::SeqDat(
@@ -154,6 +254,8 @@ mod tests {
             PathMatch {
                 path: PathBuf::from("./"),
                 matches: Vec::new(),
+                skipped: false,
+                contents: None,
             },
         ));
         let _ = in_tx.send(PipelineInfo::SeqEnd(3));
@@ -170,5 +272,92 @@ mod tests {
         assert_eq!(ret[0], 0);
         assert_eq!(ret[1], 1);
         assert_eq!(ret[2], 2);
+        assert_eq!(gate.next(), 3);
+    }
+
+    #[test]
+    fn pipeline_sorter_join() {
+        let mut sorter = PipelineSorter::new(2);
+
+        let (in_tx0, in_rx0) = unbounded();
+        let (in_tx1, in_rx1) = unbounded();
+        let (out_tx, out_rx) = unbounded();
+        thread::spawn(move || {
+            sorter.setup(0, vec![in_rx0, in_rx1], out_tx);
+        });
+
+        let path_match = PathMatch {
+            path: PathBuf::from("./"),
+            matches: Vec::new(),
+            skipped: false,
+            contents: None,
+        };
+
+        // The first input finishes before the second one begins
+        let _ = in_tx0.send(PipelineInfo::SeqBeg(0));
+        let _ = in_tx0.send(PipelineInfo::SeqDat(1, path_match.clone()));
+        let _ = in_tx0.send(PipelineInfo::SeqEnd(3));
+        drop(in_tx0);
+        let _ = in_tx1.send(PipelineInfo::MsgInfo(
+            1,
+            PipelineNotice::ParentGitignore(PathBuf::from("./")),
+        ));
+        let _ = in_tx1.send(PipelineInfo::SeqDat(2, path_match.clone()));
+        let _ = in_tx1.send(PipelineInfo::SeqDat(0, path_match.clone()));
+        let _ = in_tx1.send(PipelineInfo::SeqEnd(3));
+
+        let mut ret = Vec::new();
+        loop {
+            match out_rx.recv().unwrap() {
+                PipelineInfo::SeqDat(x, _) => ret.push(x),
+                PipelineInfo::SeqEnd(_) => break,
+                _ => (),
+            }
+        }
+
+        assert_eq!(ret, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn pipeline_sorter_count() {
+        let mut sorter = PipelineSorter::new(1);
+        sorter.sort_key = SortKey::Count;
+        sorter.sort_reverse = true;
+
+        let (in_tx, in_rx) = unbounded();
+        let (out_tx, out_rx) = unbounded();
+        thread::spawn(move || {
+            sorter.setup(0, vec![in_rx], out_tx);
+        });
+
+        let _ = in_tx.send(PipelineInfo::SeqBeg(0));
+        for (i, n) in [1, 3, 0, 3].iter().enumerate() {
+            let m = Match {
+                beg: 0,
+                end: 0,
+                sub_match: Vec::new(),
+            };
+            let _ = in_tx.send(PipelineInfo::SeqDat(
+                i,
+                PathMatch {
+                    path: PathBuf::from("./"),
+                    matches: vec![m; *n],
+                    skipped: false,
+                    contents: None,
+                },
+            ));
+        }
+        let _ = in_tx.send(PipelineInfo::SeqEnd(4));
+
+        let mut ret = Vec::new();
+        loop {
+            match out_rx.recv().unwrap() {
+                PipelineInfo::SeqDat(x, _) => ret.push(x),
+                PipelineInfo::SeqEnd(_) => break,
+                _ => (),
+            }
+        }
+
+        assert_eq!(ret, vec![1, 3, 0, 2]);
     }
 }
@@ -1,6 +1,7 @@
 use crate::console::Console;
+use std::env;
 use std::fs::File;
-use std::io::{BufReader, Error, ErrorKind, Read};
+use std::io::{self, BufReader, Error, ErrorKind, IsTerminal, Read};
 use std::path::PathBuf;
 use std::process;
 use std::time::{Duration, Instant};
@@ -9,7 +10,6 @@ use std::time::{Duration, Instant};
 // Utility
 // ---------------------------------------------------------------------------------------------------------------------
 
-#[cfg(feature = "statistics")]
 macro_rules! watch_time (
     ( $total:expr, $func:block ) => (
         {
@@ -20,15 +20,6 @@ macro_rules! watch_time (
     );
 );
 
-#[cfg(not(feature = "statistics"))]
-macro_rules! watch_time (
-    ( $total:expr, $func:block ) => (
-        {
-            $func;
-        }
-    );
-);
-
 pub fn watch_time<F>(closure: F) -> Duration
 where
     F: FnOnce(),
@@ -60,8 +51,44 @@ pub fn read_from_file(path: &str) -> Result<Vec<u8>, Error> {
     Ok(ret.into_bytes())
 }
 
-pub fn decode_error(e: ErrorKind) -> &'static str {
-    match e {
+pub fn read_path_list(path: &str, null_separated: bool) -> Result<Vec<PathBuf>, Error> {
+    let mut buf = Vec::new();
+    if path == "-" {
+        io::stdin().read_to_end(&mut buf)?;
+    } else {
+        File::open(path)?.read_to_end(&mut buf)?;
+    }
+
+    let separator = if null_separated { 0x00 } else { 0x0a };
+    let mut ret = Vec::new();
+    for name in buf.split(|x| *x == separator) {
+        let name = if !null_separated && name.last() == Some(&0x0d) {
+            &name[..name.len() - 1]
+        } else {
+            name
+        };
+        if !name.is_empty() {
+            ret.push(bytes_to_path(name));
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(unix)]
+pub fn bytes_to_path(name: &[u8]) -> PathBuf {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(name))
+}
+
+#[cfg(not(unix))]
+pub fn bytes_to_path(name: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(name).into_owned())
+}
+
+/// Describe an error by its kind, or by the message of the OS if the kind has no own description.
+pub fn decode_error(e: &Error) -> String {
+    let s = match e.kind() {
         ErrorKind::NotFound => "file not found",
         ErrorKind::PermissionDenied => "permission denied",
         ErrorKind::ConnectionRefused => "connection refused",
@@ -78,18 +105,9 @@ pub fn decode_error(e: ErrorKind) -> &'static str {
         ErrorKind::TimedOut => "operation timeout",
         ErrorKind::WriteZero => "write size is zero",
         ErrorKind::Interrupted => "interrupted",
-        ErrorKind::Other => "unknown",
-        _ => "unknown",
-    }
-}
-
-pub enum PipelineInfo<T> {
-    Beg(usize),
-    Ok(T),
-    Info(String),
-    Err(String),
-    Time(Duration, Duration),
-    End(usize),
+        _ => return e.to_string(),
+    };
+    String::from(s)
 }
 
 pub fn exit(code: i32, console: &mut Console) -> ! {
@@ -106,6 +124,16 @@ pub fn handle_escape(text: &str) -> String {
     text
 }
 
+/// Check whether `--color auto` uses colors, which needs stdout to be a terminal and `NO_COLOR` not to be set.
+pub fn is_color_auto() -> bool {
+    env::var_os("NO_COLOR").map_or(true, |x| x.is_empty()) && io::stdout().is_terminal()
+}
+
+/// Same as `is_color_auto` for stderr, which may be a terminal even if stdout is redirected.
+pub fn is_color_auto_stderr() -> bool {
+    env::var_os("NO_COLOR").map_or(true, |x| x.is_empty()) && io::stderr().is_terminal()
+}
+
 pub fn get_config(name: &str) -> Option<PathBuf> {
     let dot_cfg_path = directories::BaseDirs::new()
         .map(|base| base.home_dir().join(&format!(".{}", name)))
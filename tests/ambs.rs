@@ -0,0 +1,222 @@
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use tempfile::TempDir;
+
+// ---------------------------------------------------------------------------------------------------------------------
+// Utility
+// ---------------------------------------------------------------------------------------------------------------------
+
+/// Create files to search, where "abc" is found 3 times in a.txt and once in b.txt.
+fn setup() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "abc\nabc abc\n").unwrap();
+    fs::write(dir.path().join("b.txt"), "abc\n").unwrap();
+    fs::write(dir.path().join("c.txt"), "xyz\n").unwrap();
+    dir
+}
+
+fn ambs(dir: &Path, args: &[&str]) -> Output {
+    command(dir, args).output().unwrap()
+}
+
+fn command(dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_ambs"));
+    command
+        .current_dir(dir)
+        // ambs.toml of the user is not read
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .args(["--sort", "path"])
+        .args(args);
+    // The console needs a terminfo even if the output is not a terminal
+    if env::var_os("TERM").is_none() {
+        command.env("TERM", "dumb");
+    }
+    command
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+// ---------------------------------------------------------------------------------------------------------------------
+// Test
+// ---------------------------------------------------------------------------------------------------------------------
+
+#[test]
+fn exit_code() {
+    let dir = setup();
+    let dir = dir.path();
+
+    assert_eq!(ambs(dir, &["abc", "a.txt", "c.txt"]).status.code(), Some(0));
+    assert_eq!(ambs(dir, &["zzz", "a.txt", "c.txt"]).status.code(), Some(1));
+    // An error is prior to the matches
+    assert_eq!(ambs(dir, &["abc", "a.txt", "none.txt"]).status.code(), Some(2));
+    assert_eq!(ambs(dir, &["zzz", "a.txt", "none.txt"]).status.code(), Some(2));
+    // except with -q, which can tell that any match is found
+    assert_eq!(ambs(dir, &["-q", "abc", "a.txt", "none.txt"]).status.code(), Some(0));
+    assert_eq!(ambs(dir, &["-q", "zzz", "a.txt", "none.txt"]).status.code(), Some(2));
+}
+
+#[test]
+fn count() {
+    let dir = setup();
+    let dir = dir.path();
+
+    let output = ambs(dir, &["-c", "abc", "a.txt", "b.txt", "c.txt"]);
+    assert_eq!(stdout(&output), "a.txt:3\nb.txt:1\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = ambs(dir, &["-c", "zzz", "a.txt", "b.txt", "c.txt"]);
+    assert_eq!(stdout(&output), "");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn files_with_matches() {
+    let dir = setup();
+    let dir = dir.path();
+
+    let output = ambs(dir, &["-l", "abc", "a.txt", "b.txt", "c.txt"]);
+    assert_eq!(stdout(&output), "a.txt\nb.txt\n");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn count_total() {
+    let dir = setup();
+    let dir = dir.path();
+
+    let output = ambs(dir, &["--count-total", "abc", "a.txt", "b.txt", "c.txt"]);
+    assert_eq!(stdout(&output), "4\n");
+
+    let output = ambs(dir, &["-c", "--count-total", "abc", "a.txt", "b.txt", "c.txt"]);
+    assert_eq!(stdout(&output), "a.txt:3\nb.txt:1\n4\n");
+
+    let output = ambs(dir, &["--count-total", "zzz", "a.txt", "b.txt", "c.txt"]);
+    assert_eq!(stdout(&output), "0\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn quiet() {
+    let dir = setup();
+    let dir = dir.path();
+
+    let output = ambs(dir, &["-q", "abc", "a.txt", "b.txt", "c.txt"]);
+    assert_eq!(stdout(&output), "");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = ambs(dir, &["-q", "--count-total", "abc", "a.txt", "b.txt", "c.txt"]);
+    assert_eq!(stdout(&output), "");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = ambs(dir, &["-q", "zzz", "a.txt", "b.txt", "c.txt"]);
+    assert_eq!(stdout(&output), "");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn capture_group() {
+    let dir = setup();
+    let dir = dir.path();
+
+    let output = ambs(
+        dir,
+        &["--regex", "-o", "--capture-group", "1", "a(b)|x(y)", "a.txt", "c.txt"],
+    );
+    assert_eq!(stdout(&output), "a.txt:b\na.txt:b\na.txt:b\n");
+
+    let output = ambs(
+        dir,
+        &["--regex", "-o", "--capture-group", "2", "a(b)|x(y)", "a.txt", "c.txt"],
+    );
+    assert_eq!(stdout(&output), "c.txt:y\n");
+
+    let output = ambs(
+        dir,
+        &["--regex", "-o", "--capture-group", "3", "a(b)|x(y)", "a.txt", "c.txt"],
+    );
+    assert_eq!(stdout(&output), "");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("out of range"));
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn vimgrep() {
+    let dir = setup();
+    let dir = dir.path();
+    fs::write(dir.join("u.txt"), "αβ abc\n\tabc\n").unwrap();
+
+    // Every match is printed with its line, even if the line has another match
+    let output = ambs(dir, &["--vimgrep", "abc", "a.txt", "c.txt"]);
+    assert_eq!(stdout(&output), "a.txt:1:1:abc\na.txt:2:1:abc abc\na.txt:2:5:abc abc\n");
+
+    // The column is counted in characters, where a tab is a character, regardless of --column-unit
+    let expected = "u.txt:1:4:αβ abc\nu.txt:2:2:\tabc\n";
+    assert_eq!(stdout(&ambs(dir, &["--vimgrep", "abc", "u.txt"])), expected);
+    assert_eq!(
+        stdout(&ambs(dir, &["--vimgrep", "--column-unit", "byte", "abc", "u.txt"])),
+        expected
+    );
+}
+
+#[test]
+fn format() {
+    let dir = setup();
+    let dir = dir.path();
+
+    let output = ambs(dir, &["--regex", "--format", "{path}:{pattern}:{1}", "a(b)c", "b.txt"]);
+    assert_eq!(stdout(&output), "b.txt:a(b)c:b\n");
+
+    // A capture group can't be rendered without regex search, or out of the regex
+    let output = ambs(dir, &["--format", "{path}:{1}", "abc", "b.txt"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = ambs(dir, &["--regex", "--format", "{path}:{2}", "a(b)c", "b.txt"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("out of range"));
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn color() {
+    let dir = setup();
+    let dir = dir.path();
+
+    // auto doesn't use colors through a pipe
+    assert_eq!(stdout(&ambs(dir, &["abc", "b.txt"])), "b.txt:abc\n");
+    assert_eq!(stdout(&ambs(dir, &["--color=never", "abc", "b.txt"])), "b.txt:abc\n");
+    assert!(stdout(&ambs(dir, &["--color=always", "abc", "b.txt"])).contains('\x1b'));
+    // Bare --color means always, and doesn't take the following keyword
+    let output = ambs(dir, &["--color", "abc", "b.txt"]);
+    assert!(stdout(&output).contains('\x1b'));
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&ambs(dir, &["abc", "b.txt", "--color"])).contains('\x1b'));
+}
+
+#[test]
+fn closed_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    // The output is larger than the buffer of the pipe
+    for i in 0..1000 {
+        fs::write(dir.join(format!("{}.txt", i)), format!("abc {}\n", "x".repeat(1000))).unwrap();
+    }
+
+    let mut child = command(dir, &["--statistics", "abc"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut buf = [0; 1];
+    stdout.read_exact(&mut buf).unwrap();
+    drop(stdout);
+
+    // The search is stopped, and the statistics and the exit code are given as usual
+    let output = child.wait_with_output().unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Statistics"));
+    assert_eq!(output.status.code(), Some(0));
+}